@@ -189,7 +189,12 @@ impl<W: Write + Seek> GrfArchiveBuilder<W> {
         let v_file_count = i32::try_from(self.entries.len() + 7)?;
         let file_table_offset = match self.version_major {
             2 => self.write_grf_table_200()?,
-            1 => std::unimplemented!(), // TODO(LinkZ): Implement
+            // TODO(LinkZ): Implement serialization of 1.x archives
+            1 => {
+                return Err(GrufError::serialization_error(
+                    "Serialization of GRF 1.x archives is not supported",
+                ))
+            }
             _ => return Err(GrufError::serialization_error("Wrong file format version")),
         };
         // Update the header
@@ -350,6 +355,17 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_finish_unsupported_version() {
+        let temp_dir = tempdir().unwrap();
+        let output_file = File::create(temp_dir.path().join("103-builder.grf")).unwrap();
+        let mut builder = GrfArchiveBuilder::create(output_file, 1, 3).unwrap();
+        let err = builder.finish().unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("GRF 1.x archives is not supported"));
+    }
+
     #[test]
     fn test_import_raw_entry_from_grf() {
         let grf_dir_path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources/tests/grf");
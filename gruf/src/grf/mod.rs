@@ -2,7 +2,7 @@ pub mod builder;
 pub mod reader;
 
 pub use builder::GrfArchiveBuilder;
-pub use reader::{GrfArchive, GrfFileEntry};
+pub use reader::{GrfArchive, GrfFileEncryption, GrfFileEntry};
 
 mod crypto;
 mod dyn_alloc;
@@ -1,6 +1,7 @@
 mod archive;
 mod error;
 pub mod grf;
+pub mod rgz;
 pub mod thor;
 
 pub use error::{GrufError, Result};
@@ -0,0 +1,12 @@
+pub mod reader;
+
+pub use reader::{RgzArchive, RgzEntry};
+
+use reader::RGZ_HEADER_MAGIC;
+
+/// Returns `true` if `header` looks like the beginning of an RGZ archive.
+///
+/// RGZ archives are gzip-compressed, their header is the gzip header.
+pub fn is_rgz_header(header: &[u8]) -> bool {
+    header.starts_with(RGZ_HEADER_MAGIC)
+}
@@ -0,0 +1,216 @@
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::{GrufError, Result};
+use encoding::label::encoding_from_whatwg_label;
+use encoding::DecoderTrap;
+use flate2::read::GzDecoder;
+
+pub const RGZ_HEADER_MAGIC: &[u8; 2] = b"\x1f\x8b";
+const ENTRY_TYPE_FILE: u8 = b'f';
+const ENTRY_TYPE_DIRECTORY: u8 = b'd';
+const ENTRY_TYPE_END: u8 = b'e';
+
+/// Entry of an RGZ archive.
+#[derive(Debug, PartialEq, Eq)]
+pub enum RgzEntry {
+    File {
+        relative_path: String,
+        content: Vec<u8>,
+    },
+    Directory {
+        relative_path: String,
+    },
+}
+
+/// Sequential reader for RGZ archives.
+///
+/// RGZ archives are gzip streams that contain a list of entries, which can
+/// only be read in order. Each entry is made of a type ('f' for files, 'd'
+/// for directories and 'e' for the end of the archive), a length-prefixed
+/// NUL-terminated path and, for files, a length-prefixed content.
+pub struct RgzArchive<R: Read> {
+    decoder: GzDecoder<R>,
+    finished: bool,
+}
+
+impl RgzArchive<File> {
+    pub fn open<P: AsRef<Path>>(rgz_archive_path: P) -> Result<Self> {
+        let file = File::open(rgz_archive_path)?;
+        Ok(Self::new(file))
+    }
+}
+
+impl<R: Read> RgzArchive<R> {
+    pub fn new(obj: R) -> Self {
+        Self {
+            decoder: GzDecoder::new(obj),
+            finished: false,
+        }
+    }
+
+    /// Reads the next entry of the archive.
+    ///
+    /// Returns `None` once the end of the archive has been reached.
+    pub fn next_entry(&mut self) -> Result<Option<RgzEntry>> {
+        if self.finished {
+            return Ok(None);
+        }
+        let entry = self.read_entry();
+        // Note: Entries cannot be read anymore after an error
+        if !matches!(entry, Ok(Some(_))) {
+            self.finished = true;
+        }
+        entry
+    }
+
+    fn read_entry(&mut self) -> Result<Option<RgzEntry>> {
+        let mut entry_type = [0; 1];
+        if let Err(e) = self.decoder.read_exact(&mut entry_type) {
+            return match e.kind() {
+                // Note: Some archives don't have an end entry
+                io::ErrorKind::UnexpectedEof => Ok(None),
+                _ => Err(e.into()),
+            };
+        }
+        let relative_path = self.read_path()?;
+        match entry_type[0] {
+            ENTRY_TYPE_FILE => {
+                let content_size = usize::try_from(self.read_u32()?)?;
+                let mut content = Vec::with_capacity(content_size);
+                let read_size = self
+                    .decoder
+                    .by_ref()
+                    .take(content_size as u64)
+                    .read_to_end(&mut content)?;
+                if read_size != content_size {
+                    return Err(GrufError::parsing_error("Unexpected end of archive"));
+                }
+                Ok(Some(RgzEntry::File {
+                    relative_path,
+                    content,
+                }))
+            }
+            ENTRY_TYPE_DIRECTORY => Ok(Some(RgzEntry::Directory { relative_path })),
+            ENTRY_TYPE_END => Ok(None),
+            _ => Err(GrufError::parsing_error("Unknown entry type")),
+        }
+    }
+
+    fn read_path(&mut self) -> Result<String> {
+        let mut path_size = [0; 1];
+        self.decoder.read_exact(&mut path_size)?;
+        let mut path = vec![0; path_size[0] as usize];
+        self.decoder.read_exact(&mut path)?;
+        // Remove the NUL char terminator
+        if let Some(nul_position) = path.iter().position(|c| *c == 0) {
+            path.truncate(nul_position);
+        }
+        string_from_win_1252(&path)
+    }
+
+    fn read_u32(&mut self) -> Result<u32> {
+        let mut value = [0; 4];
+        self.decoder.read_exact(&mut value)?;
+        Ok(u32::from_le_bytes(value))
+    }
+}
+
+impl<R: Read> Iterator for RgzArchive<R> {
+    type Item = Result<RgzEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entry().transpose()
+    }
+}
+
+fn string_from_win_1252(v: &[u8]) -> Result<String> {
+    let decoder = encoding_from_whatwg_label("windows-1252")
+        .ok_or_else(|| GrufError::parsing_error("Decoder unavailable"))?;
+    decoder
+        .decode(v, DecoderTrap::Strict)
+        .map_err(GrufError::parsing_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    fn encode_entry(entry_type: u8, relative_path: &str, content: Option<&[u8]>) -> Vec<u8> {
+        let mut data = vec![entry_type, relative_path.len() as u8 + 1];
+        data.extend_from_slice(relative_path.as_bytes());
+        data.push(0);
+        if let Some(content) = content {
+            data.extend_from_slice(&(content.len() as u32).to_le_bytes());
+            data.extend_from_slice(content);
+        }
+        data
+    }
+
+    fn make_rgz_archive(entries: &[Vec<u8>]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        for entry in entries {
+            encoder.write_all(entry).unwrap();
+        }
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_read_entries() {
+        let rgz_content = make_rgz_archive(&[
+            encode_entry(b'd', "data", None),
+            encode_entry(b'f', "data\\clientinfo.xml", Some(b"<clientinfo/>")),
+            encode_entry(b'f', "data\\empty.txt", Some(b"")),
+            encode_entry(b'e', "end", None),
+        ]);
+        assert!(crate::rgz::is_rgz_header(&rgz_content));
+
+        let entries: Vec<RgzEntry> = RgzArchive::new(rgz_content.as_slice())
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                RgzEntry::Directory {
+                    relative_path: "data".to_string()
+                },
+                RgzEntry::File {
+                    relative_path: "data\\clientinfo.xml".to_string(),
+                    content: b"<clientinfo/>".to_vec()
+                },
+                RgzEntry::File {
+                    relative_path: "data\\empty.txt".to_string(),
+                    content: vec![]
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_entries_without_end() {
+        let rgz_content = make_rgz_archive(&[encode_entry(b'f', "a.txt", Some(b"a"))]);
+        let mut rgz_archive = RgzArchive::new(rgz_content.as_slice());
+        assert!(rgz_archive.next_entry().unwrap().is_some());
+        assert!(rgz_archive.next_entry().unwrap().is_none());
+        assert!(rgz_archive.next_entry().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_read_truncated_entry() {
+        let mut entry = encode_entry(b'f', "a.txt", Some(b"abcdef"));
+        entry.truncate(entry.len() - 2);
+        let rgz_content = make_rgz_archive(&[entry]);
+        let mut rgz_archive = RgzArchive::new(rgz_content.as_slice());
+        assert!(rgz_archive.next_entry().is_err());
+        assert!(rgz_archive.next_entry().unwrap().is_none());
+
+        let rgz_content = make_rgz_archive(&[encode_entry(b'x', "a.txt", None)]);
+        let mut rgz_archive = RgzArchive::new(rgz_content.as_slice());
+        assert!(rgz_archive.next_entry().is_err());
+    }
+}
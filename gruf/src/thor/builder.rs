@@ -1,23 +1,31 @@
 use std::boxed::Box;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::convert::TryFrom;
+use std::fs::{File, OpenOptions};
 use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::Path;
 
 use crate::archive::{serialize_as_win1252_str_into, GenericFileEntry};
-use crate::thor::{ThorMode, MULTIPLE_FILES_TABLE_DESC_SIZE, THOR_HEADER_MAGIC};
-use crate::Result;
+use crate::thor::{ThorArchive, ThorMode, MULTIPLE_FILES_TABLE_DESC_SIZE, THOR_HEADER_MAGIC};
+use crate::{GrufError, Result};
 use flate2::write::ZlibEncoder;
 use flate2::Compression;
 use serde::Serialize;
 
 const THOR_HEADER_FIXED_SIZE: usize = THOR_HEADER_MAGIC.len() + 0x8;
+const MAX_COMPRESSION_LEVEL: u32 = 9;
 
 pub struct ThorArchiveBuilder<W: Write + Seek> {
     obj: Box<W>,
-    entries: HashMap<String, Option<GenericFileEntry>>,
+    // Note: Ordered by path so that the file table is reproducible
+    entries: BTreeMap<String, Option<GenericFileEntry>>,
+    // Paths of the entries of the opened archive that haven't been replaced
+    original_paths: BTreeSet<String>,
+    replaced_paths: Vec<String>,
     finished: bool,
     use_grf_merging: bool,
     target_grf_name: String,
+    compression: Compression,
 }
 
 #[derive(Debug, Serialize)]
@@ -49,7 +57,29 @@ pub struct SerializableThorFileEntryAdd {
 }
 
 impl<W: Write + Seek> ThorArchiveBuilder<W> {
-    pub fn new(mut obj: W, use_grf_merging: bool, target_grf_name: Option<String>) -> Result<Self> {
+    pub fn new(obj: W, use_grf_merging: bool, target_grf_name: Option<String>) -> Result<Self> {
+        Self::with_compression_level(
+            obj,
+            use_grf_merging,
+            target_grf_name,
+            Compression::default().level(),
+        )
+    }
+
+    /// Same as `new` but allows choosing the zlib compression level (from 0 to
+    /// 9) used for the archive's content.
+    pub fn with_compression_level(
+        mut obj: W,
+        use_grf_merging: bool,
+        target_grf_name: Option<String>,
+        compression_level: u32,
+    ) -> Result<Self> {
+        if compression_level > MAX_COMPRESSION_LEVEL {
+            return Err(GrufError::serialization_error(format!(
+                "Invalid compression level: {}",
+                compression_level
+            )));
+        }
         let target_grf_name = target_grf_name.unwrap_or_default();
         // Placeholder for the THOR header
         let place_holder =
@@ -60,16 +90,42 @@ impl<W: Write + Seek> ThorArchiveBuilder<W> {
         obj.write_all(place_holder.as_slice())?;
         Ok(Self {
             obj: Box::new(obj),
-            entries: HashMap::new(),
+            entries: BTreeMap::new(),
+            original_paths: BTreeSet::new(),
+            replaced_paths: Vec::new(),
             finished: false,
             use_grf_merging,
             target_grf_name,
+            compression: Compression::new(compression_level),
         })
     }
 
-    pub fn append_file_update<R: Read>(&mut self, entry_path: String, mut data: R) -> Result<()> {
+    pub fn append_file_update<R: Read>(&mut self, entry_path: String, data: R) -> Result<()> {
+        self.append_file_update_with_compression(entry_path, data, self.compression)
+    }
+
+    /// Same as `append_file_update` but stores the file's content without
+    /// compressing it, which is useful for files that are already compressed
+    /// (e.g., PNG or OGG files).
+    ///
+    /// Note: The content is still wrapped in a zlib stream (made of "stored"
+    /// blocks), as expected by THOR readers.
+    pub fn append_stored_file_update<R: Read>(
+        &mut self,
+        entry_path: String,
+        data: R,
+    ) -> Result<()> {
+        self.append_file_update_with_compression(entry_path, data, Compression::none())
+    }
+
+    fn append_file_update_with_compression<R: Read>(
+        &mut self,
+        entry_path: String,
+        mut data: R,
+        compression: Compression,
+    ) -> Result<()> {
         // Compress it
-        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        let mut encoder = ZlibEncoder::new(Vec::new(), compression);
         let data_size = io::copy(data.by_ref(), &mut encoder)?;
         // Write compressed data
         let compressed_data = encoder.finish()?;
@@ -78,6 +134,7 @@ impl<W: Write + Seek> ThorArchiveBuilder<W> {
         let offset = self.obj.seek(SeekFrom::Current(0))?;
         let mut compressed_reader = Cursor::new(compressed_data);
         let _ = io::copy(&mut compressed_reader, self.obj.by_ref())?;
+        self.track_replacement(&entry_path);
         self.entries.insert(
             entry_path,
             Some(GenericFileEntry {
@@ -90,9 +147,22 @@ impl<W: Write + Seek> ThorArchiveBuilder<W> {
     }
 
     pub fn append_file_removal(&mut self, entry_path: String) {
+        self.track_replacement(&entry_path);
         self.entries.insert(entry_path, None);
     }
 
+    /// Returns the paths of the entries of the opened archive (see `open`)
+    /// that have been replaced by appended entries, in order of replacement.
+    pub fn replaced_paths(&self) -> &[String] {
+        &self.replaced_paths
+    }
+
+    fn track_replacement(&mut self, entry_path: &str) {
+        if self.original_paths.remove(entry_path) {
+            self.replaced_paths.push(entry_path.to_string());
+        }
+    }
+
     pub fn finish(&mut self) -> Result<()> {
         if self.finished {
             return Ok(());
@@ -139,7 +209,7 @@ impl<W: Write + Seek> ThorArchiveBuilder<W> {
             }
         }
         // Compress the table
-        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        let mut encoder = ZlibEncoder::new(Vec::new(), self.compression);
         encoder.write_all(&table)?;
         let compressed_table = encoder.finish()?;
         let compressed_table_size = compressed_table.len();
@@ -151,6 +221,53 @@ impl<W: Write + Seek> ThorArchiveBuilder<W> {
     }
 }
 
+impl ThorArchiveBuilder<File> {
+    /// Opens an existing THOR archive in order to append entries to it.
+    ///
+    /// Entries that are appended with a path that already exists in the
+    /// archive replace the original entries (see `replaced_paths`).
+    ///
+    /// New content is written at the end of the file and the header is only
+    /// updated by `finish`, so that the original archive stays readable if
+    /// it's never called (e.g., after a crash). The original file table is
+    /// left in place as unused space.
+    pub fn open<P: AsRef<Path>>(thor_archive_path: P) -> Result<Self> {
+        let thor_archive = ThorArchive::open(thor_archive_path.as_ref())?;
+        let target_grf_name = thor_archive.target_grf_name();
+        let mut entries = BTreeMap::new();
+        for entry in thor_archive.get_entries() {
+            if entry.is_removed {
+                entries.insert(entry.relative_path.clone(), None);
+                continue;
+            }
+            entries.insert(
+                entry.relative_path.clone(),
+                Some(GenericFileEntry {
+                    offset: entry.offset,
+                    size: u32::try_from(entry.size)?,
+                    size_compressed: u32::try_from(entry.size_compressed)?,
+                }),
+            );
+        }
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(thor_archive_path)?;
+        file.seek(SeekFrom::End(0))?;
+        Ok(Self {
+            obj: Box::new(file),
+            original_paths: entries.keys().cloned().collect(),
+            entries,
+            replaced_paths: Vec::new(),
+            finished: false,
+            use_grf_merging: thor_archive.use_grf_merging(),
+            target_grf_name,
+            compression: Compression::default(),
+        })
+    }
+}
+
 impl<W: Write + Seek> Drop for ThorArchiveBuilder<W> {
     // Automatically call finish on destruction
     fn drop(&mut self) {
@@ -217,7 +334,8 @@ fn serialize_thor_slice_into<W: Write>(mut writer: W, slice: &[u8]) -> Result<()
 mod tests {
     use super::*;
     use crate::thor::{ThorArchive, ThorFileEntry};
-    use std::fs::File;
+    use std::collections::HashMap;
+    use std::fs::{self, File};
     use tempfile::tempdir;
 
     #[test]
@@ -312,4 +430,151 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_open() {
+        let temp_dir = tempdir().unwrap();
+        let output_path = temp_dir.path().join("builder.thor");
+        {
+            let output_file = File::create(&output_path).unwrap();
+            let grf_name = "myserver.grf";
+            let mut builder =
+                ThorArchiveBuilder::new(output_file, true, Some(grf_name.to_string())).unwrap();
+            builder
+                .append_file_update("data\\test1".to_string(), vec![1, 2, 3].as_slice())
+                .unwrap();
+            builder
+                .append_file_update("data\\test2".to_string(), vec![5, 6].as_slice())
+                .unwrap();
+            builder.append_file_removal("data\\test3".to_string());
+        }
+        {
+            let mut builder = ThorArchiveBuilder::open(&output_path).unwrap();
+            // Replace an existing entry
+            builder
+                .append_file_update("data\\test2".to_string(), vec![7, 8, 9, 10].as_slice())
+                .unwrap();
+            builder
+                .append_file_update("data\\test4".to_string(), vec![11].as_slice())
+                .unwrap();
+            builder.append_file_removal("data\\test5".to_string());
+            builder.append_file_removal("data\\test3".to_string());
+            assert_eq!(builder.replaced_paths(), ["data\\test2", "data\\test3"]);
+        }
+        {
+            // Interrupted appends leave the original archive untouched
+            let mut builder = ThorArchiveBuilder::open(&output_path).unwrap();
+            builder
+                .append_file_update("data\\test1".to_string(), vec![0].as_slice())
+                .unwrap();
+            std::mem::forget(builder);
+        }
+        let mut thor_archive = ThorArchive::open(&output_path).unwrap();
+        assert_eq!(thor_archive.file_count(), 5);
+        assert_eq!(thor_archive.target_grf_name(), "myserver.grf");
+        assert!(thor_archive.use_grf_merging());
+        assert_eq!(
+            thor_archive.read_file_content("data\\test1").unwrap(),
+            vec![1, 2, 3]
+        );
+        assert_eq!(
+            thor_archive.read_file_content("data\\test2").unwrap(),
+            vec![7, 8, 9, 10]
+        );
+        assert_eq!(
+            thor_archive.read_file_content("data\\test4").unwrap(),
+            vec![11]
+        );
+        assert!(
+            thor_archive
+                .get_file_entry("data\\test3")
+                .unwrap()
+                .is_removed
+        );
+        assert!(
+            thor_archive
+                .get_file_entry("data\\test5")
+                .unwrap()
+                .is_removed
+        );
+    }
+
+    #[test]
+    fn test_reproducible_output() {
+        let temp_dir = tempdir().unwrap();
+        let build_archive = |output_path| {
+            let output_file = File::create(output_path).unwrap();
+            let mut builder = ThorArchiveBuilder::new(output_file, true, None).unwrap();
+            for i in 0..32 {
+                builder
+                    .append_file_update(format!("data\\file{}", i), vec![i as u8; 64].as_slice())
+                    .unwrap();
+                builder.append_file_removal(format!("data\\removed{}", i));
+            }
+        };
+        let output_path1 = temp_dir.path().join("builder1.thor");
+        let output_path2 = temp_dir.path().join("builder2.thor");
+        build_archive(&output_path1);
+        build_archive(&output_path2);
+        assert_eq!(
+            fs::read(output_path1).unwrap(),
+            fs::read(output_path2).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_append_stored_file_update() {
+        let temp_dir = tempdir().unwrap();
+        let output_path = temp_dir.path().join("builder.thor");
+        let expected_content: Vec<u8> = (0..4096).map(|x| (x % 7) as u8).collect();
+        {
+            let output_file = File::create(&output_path).unwrap();
+            let mut builder = ThorArchiveBuilder::new(output_file, false, None).unwrap();
+            builder
+                .append_stored_file_update("data\\stored".to_string(), expected_content.as_slice())
+                .unwrap();
+            builder
+                .append_file_update("data\\compressed".to_string(), expected_content.as_slice())
+                .unwrap();
+        }
+        let mut thor_archive = ThorArchive::open(&output_path).unwrap();
+        let stored_entry = thor_archive.get_file_entry("data\\stored").unwrap();
+        let compressed_entry = thor_archive.get_file_entry("data\\compressed").unwrap();
+        assert!(stored_entry.size_compressed > stored_entry.size);
+        assert!(compressed_entry.size_compressed < compressed_entry.size);
+        for entry_path in &["data\\stored", "data\\compressed"] {
+            let content = thor_archive.read_file_content(entry_path).unwrap();
+            assert_eq!(content, expected_content);
+        }
+    }
+
+    #[test]
+    fn test_compression_level() {
+        let temp_dir = tempdir().unwrap();
+        let expected_content: Vec<u8> = (0..4096).map(|x| (x % 7) as u8).collect();
+        for compression_level in &[0, 1, 9] {
+            let output_path = temp_dir
+                .path()
+                .join(format!("builder{}.thor", compression_level));
+            {
+                let output_file = File::create(&output_path).unwrap();
+                let mut builder = ThorArchiveBuilder::with_compression_level(
+                    output_file,
+                    false,
+                    None,
+                    *compression_level,
+                )
+                .unwrap();
+                builder
+                    .append_file_update("data\\test".to_string(), expected_content.as_slice())
+                    .unwrap();
+            }
+            let mut thor_archive = ThorArchive::open(&output_path).unwrap();
+            let content = thor_archive.read_file_content("data\\test").unwrap();
+            assert_eq!(content, expected_content);
+        }
+        // Out of range compression level
+        let output_file = File::create(temp_dir.path().join("invalid.thor")).unwrap();
+        assert!(ThorArchiveBuilder::with_compression_level(output_file, false, None, 10).is_err());
+    }
 }
@@ -3,11 +3,20 @@ pub mod reader;
 
 pub use builder::ThorArchiveBuilder;
 pub use reader::{
-    patch_list_from_string, ThorArchive, ThorFileEntry, ThorPatchInfo, ThorPatchList,
+    checksum, patch_list_from_string, patch_list_from_string_checked, ThorArchive, ThorFileEntry,
+    ThorPatchInfo, ThorPatchList,
 };
 
 use reader::THOR_HEADER_MAGIC;
 
+/// Size of the magic bytes at the beginning of THOR archives.
+pub const THOR_HEADER_MAGIC_SIZE: usize = THOR_HEADER_MAGIC.len();
+
+/// Returns `true` if `header` looks like the beginning of a THOR archive.
+pub fn is_thor_header(header: &[u8]) -> bool {
+    header.starts_with(THOR_HEADER_MAGIC)
+}
+
 const MULTIPLE_FILES_TABLE_DESC_SIZE: usize = 2 * std::mem::size_of::<i32>();
 #[derive(Debug, PartialEq, Eq)]
 enum ThorMode {
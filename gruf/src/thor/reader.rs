@@ -25,14 +25,30 @@ pub type ThorPatchList = Vec<ThorPatchInfo>;
 
 /// Parses Thor's plist.txt file
 pub fn patch_list_from_string(content: &str) -> ThorPatchList {
-    let vec_lines: Vec<&str> = content.lines().collect();
-    let mut sorted_patch_list: ThorPatchList = vec_lines
-        .into_iter()
-        .filter_map(|elem| ThorPatchInfo::from_string(&elem))
-        .collect();
+    patch_list_from_string_checked(content).0
+}
+
+/// Parses Thor's plist.txt file and reports invalid lines.
+///
+/// Blank lines and comments (lines starting with "//") are ignored. Returns
+/// the patch list along with the numbers (starting from 1) of the lines that
+/// couldn't be parsed.
+pub fn patch_list_from_string_checked(content: &str) -> (ThorPatchList, Vec<usize>) {
+    let mut sorted_patch_list: ThorPatchList = Vec::new();
+    let mut invalid_lines = Vec::new();
+    for (line_index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("//") {
+            continue;
+        }
+        match ThorPatchInfo::from_string(line) {
+            Some(patch_info) => sorted_patch_list.push(patch_info),
+            None => invalid_lines.push(line_index + 1),
+        }
+    }
     // Sort patch list by index
     sorted_patch_list.sort_by(|a, b| a.index.cmp(&b.index));
-    sorted_patch_list
+    (sorted_patch_list, invalid_lines)
 }
 
 #[derive(Debug)]
@@ -62,6 +78,11 @@ impl ThorPatchInfo {
     }
 }
 
+/// Computes the checksum of a file's content, as stored in integrity files
+pub fn checksum(content: &[u8]) -> u32 {
+    crc32::checksum_ieee(content)
+}
+
 fn parse_data_integrity_info(data: &str) -> HashMap<&str, u32> {
     let vec_lines: Vec<_> = data.lines().collect();
     vec_lines
@@ -175,19 +196,36 @@ impl<R: Read + Seek> ThorArchive<R> {
 
     /// Checks if the container has been unintentionnaly corrupted
     pub fn is_valid(&mut self) -> Result<bool> {
+        Ok(self.corrupt_entries()?.is_empty())
+    }
+
+    /// Returns the checksums contained in the archive's integrity file, by
+    /// entry path
+    pub fn checksums(&mut self) -> Result<HashMap<String, u32>> {
         let integrity_data = self.read_file_content(INTEGRITY_FILE_NAME)?;
         let integrity_data_as_str = string_from_win_1252(integrity_data.as_slice())?;
-        let integrity_info = parse_data_integrity_info(integrity_data_as_str.as_str());
+        Ok(parse_data_integrity_info(integrity_data_as_str.as_str())
+            .into_iter()
+            .map(|(file_path, hash)| (file_path.to_string(), hash))
+            .collect())
+    }
+
+    /// Returns the paths of the entries whose content doesn't match the
+    /// checksums contained in the archive's integrity file
+    pub fn corrupt_entries(&mut self) -> Result<Vec<String>> {
+        let integrity_info = self.checksums()?;
+        let mut corrupt_entries = Vec::new();
         for (file_path, hash) in integrity_info {
-            let file_content = match self.read_file_content(file_path) {
-                Ok(v) => v,
-                Err(_) => return Ok(false),
+            let is_corrupt = match self.read_file_content(&file_path) {
+                Ok(file_content) => checksum(file_content.as_slice()) != hash,
+                Err(_) => true,
             };
-            if crc32::checksum_ieee(file_content.as_slice()) != hash {
-                return Ok(false);
+            if is_corrupt {
+                corrupt_entries.push(file_path);
             }
         }
-        Ok(true)
+        corrupt_entries.sort();
+        Ok(corrupt_entries)
     }
 }
 
@@ -440,7 +478,24 @@ pub fn parse_thor_patch<R: Seek + Read>(reader: &mut R) -> Result<ThorContainer>
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::thor::ThorArchiveBuilder;
     use std::path::PathBuf;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_patch_list_from_string_checked() {
+        let plist_content = "// Comment
+
+872 sprites_20170429.thor
+not_an_index sprites.thor
+871
+  870 iteminfo_20170423_.thor  ";
+        let (thor_patch_list, invalid_lines) = patch_list_from_string_checked(plist_content);
+        let indices: Vec<usize> = thor_patch_list.iter().map(|p| p.index).collect();
+        assert_eq!(indices, vec![870, 872]);
+        assert_eq!(thor_patch_list[0].file_name, "iteminfo_20170423_.thor");
+        assert_eq!(invalid_lines, vec![4, 5]);
+    }
 
     #[test]
     fn test_patch_list_from_string() {
@@ -575,4 +630,41 @@ mod tests {
             assert!(thor_archive.is_valid().unwrap());
         }
     }
+
+    #[test]
+    fn test_corrupt_entries() {
+        let temp_dir = tempdir().unwrap();
+        let thor_file_path = temp_dir.path().join("corrupt.thor");
+        {
+            let valid_content = vec![1u8, 2, 3];
+            let integrity_content = format!(
+                "data\\valid=0x{:08X}\r\ndata\\corrupt=0x00000000\r\ndata\\missing=0x00000000\r\n",
+                crc32::checksum_ieee(valid_content.as_slice())
+            );
+            let output_file = File::create(&thor_file_path).unwrap();
+            let mut builder = ThorArchiveBuilder::new(output_file, true, None).unwrap();
+            builder
+                .append_file_update("data\\valid".to_string(), valid_content.as_slice())
+                .unwrap();
+            builder
+                .append_file_update("data\\corrupt".to_string(), vec![4u8, 5].as_slice())
+                .unwrap();
+            builder
+                .append_file_update(
+                    INTEGRITY_FILE_NAME.to_string(),
+                    integrity_content.as_bytes(),
+                )
+                .unwrap();
+        }
+        let mut thor_archive = ThorArchive::open(&thor_file_path).unwrap();
+        let checksums = thor_archive.checksums().unwrap();
+        assert_eq!(checksums.len(), 3);
+        assert_eq!(checksums["data\\valid"], checksum(&[1, 2, 3]));
+        assert_eq!(checksums["data\\corrupt"], 0);
+        assert!(!thor_archive.is_valid().unwrap());
+        assert_eq!(
+            thor_archive.corrupt_entries().unwrap(),
+            vec!["data\\corrupt".to_string(), "data\\missing".to_string()]
+        );
+    }
 }
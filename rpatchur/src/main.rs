@@ -9,8 +9,11 @@ use std::env;
 use std::path::PathBuf;
 
 use anyhow::Result;
-use clap::{App, Arg};
-use patcher::{patcher_thread_routine, retrieve_patcher_configuration, PatcherCommand};
+use clap::{value_t, App, Arg};
+use patcher::{
+    interruptible_patcher_routine, patcher_thread_routine, retrieve_patcher_configuration,
+    startup_routine, PatchIndexRange, PatcherCommand, PatcherConfiguration,
+};
 use simple_logger::SimpleLogger;
 use tokio::{runtime, sync::mpsc};
 use ui::{UIController, WebViewUserData};
@@ -20,6 +23,9 @@ const PKG_VERSION: &str = env!("CARGO_PKG_VERSION");
 const PKG_AUTHORS: &str = env!("CARGO_PKG_AUTHORS");
 const PKG_DESCRIPTION: &str = env!("CARGO_PKG_DESCRIPTION");
 const WINDOW_TITLE: &str = "RPatchur";
+// Exit codes used in headless mode (and when printing the configuration)
+const EXIT_SUCCESS: i32 = 0;
+const EXIT_FAILURE: i32 = 1;
 
 fn main() {
     SimpleLogger::new()
@@ -41,22 +47,93 @@ fn main() {
                 .help("Sets a custom working directory")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("headless")
+                .long("headless")
+                .help("Patches the game client without opening the UI and then exits"),
+        )
+        .arg(
+            Arg::with_name("print-config")
+                .long("print-config")
+                .help("Prints the configuration as interpreted by the patcher and then exits"),
+        )
+        .arg(
+            Arg::with_name("simulate")
+                .long("simulate")
+                .requires("headless")
+                .help("Only reports what would be patched, without modifying any file"),
+        )
+        .arg(
+            Arg::with_name("from-index")
+                .long("from-index")
+                .value_name("INDEX")
+                .requires("headless")
+                .help("Only processes patches whose index is greater than or equal to INDEX")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("to-index")
+                .long("to-index")
+                .value_name("INDEX")
+                .requires("headless")
+                .help("Only processes patches whose index is less than or equal to INDEX")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("commit-index")
+                .long("commit-index")
+                .requires("headless")
+                .help("Updates the index of the last applied patch when using --from-index or --to-index"),
+        )
         .get_matches();
+    let headless = matches.is_present("headless");
+    let print_config = matches.is_present("print-config");
     if let Some(working_directory) = matches.value_of("working-directory") {
         env::set_current_dir(PathBuf::from(working_directory))
             .expect("Specified working directory is invalid or inaccessible");
     };
 
     let mut tokio_rt = build_tokio_runtime().expect("Failed to build a tokio runtime");
-    let config = match retrieve_patcher_configuration(None) {
+    let mut config = match retrieve_patcher_configuration(None) {
         Err(e) => {
             let err_msg = "Failed to retrieve the patcher's configuration";
             log::error!("{}", err_msg);
+            if headless || print_config {
+                eprintln!("Error: {}: {:#}.", err_msg, e);
+                std::process::exit(EXIT_FAILURE);
+            }
             ui::msg_box(WINDOW_TITLE, format!("<b>Error:</b> {}: {:#}.", err_msg, e));
             return;
         }
         Ok(v) => v,
     };
+    if print_config {
+        let exit_code = print_configuration(&config);
+        std::process::exit(exit_code);
+    }
+    if matches.is_present("simulate") {
+        config.patching.simulate = Some(true);
+    }
+    if matches.is_present("from-index") || matches.is_present("to-index") {
+        let index_arg = |name| {
+            matches
+                .value_of(name)
+                .map(|_| value_t!(matches, name, usize).unwrap_or_else(|e| e.exit()))
+        };
+        config.patching.index_range = Some(PatchIndexRange {
+            from_index: index_arg("from-index"),
+            to_index: index_arg("to-index"),
+            commit_index: matches.is_present("commit-index"),
+        });
+    }
+    log::info!(
+        "GRF merge strategy: {:?}",
+        config.patching.grf_merge_strategy()
+    );
+    if headless {
+        let exit_code = run_headless(&mut tokio_rt, config);
+        std::process::exit(exit_code);
+    }
     // Create a channel to allow the webview's thread to communicate with the patching thread
     let (tx, rx) = mpsc::channel::<PatcherCommand>(8);
     let webview = ui::build_webview(WINDOW_TITLE, WebViewUserData::new(config.clone(), tx))
@@ -75,6 +152,50 @@ fn main() {
     });
 }
 
+/// Runs a single patching task without the UI, the progress is printed to the
+/// standard output.
+///
+/// Returns the exit code of the process.
+fn run_headless(tokio_rt: &mut runtime::Runtime, config: PatcherConfiguration) -> i32 {
+    // Note: The sender is kept alive so that the task doesn't consider
+    // itself canceled
+    let (_tx, mut rx) = mpsc::channel::<PatcherCommand>(8);
+    let ui_controller = UIController::headless();
+    let result = tokio_rt.block_on(async {
+        let self_update_task = startup_routine(&config);
+        let result = interruptible_patcher_routine(&ui_controller, &config, &mut rx).await;
+        // Note: The process exits right after, wait for the self-update to
+        // complete
+        if let Some(self_update_task) = self_update_task {
+            let _ = self_update_task.await;
+        }
+        result
+    });
+    match result {
+        Ok(()) => EXIT_SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            EXIT_FAILURE
+        }
+    }
+}
+
+/// Prints the configuration in YAML, with credentials redacted.
+///
+/// Returns the exit code of the process.
+fn print_configuration(config: &PatcherConfiguration) -> i32 {
+    match serde_yaml::to_string(&config.redacted()) {
+        Ok(config_yaml) => {
+            println!("{}", config_yaml);
+            EXIT_SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Error: Failed to serialize the configuration: {}", e);
+            EXIT_FAILURE
+        }
+    }
+}
+
 /// Builds a tokio runtime with a threaded scheduler and a reactor
 fn build_tokio_runtime() -> Result<runtime::Runtime> {
     Ok(runtime::Builder::new()
@@ -1,7 +1,9 @@
-use std::fs::File;
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{self, File};
 use std::path::Path;
+use std::time::SystemTime;
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize)]
@@ -14,6 +16,84 @@ pub async fn read_cache_file<P: AsRef<Path>>(cache_file_path: P) -> Result<Patch
     Ok(bincode::deserialize_from(file).context("Failed to deserialize patcher cache")?)
 }
 
+/// Entry of the history of applied patches.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct PatchHistoryEntry {
+    pub index: usize,      // Index of the patch
+    pub file_name: String, // File name of the patch
+    pub applied_at: u64,   // Time at which the patch was applied (UNIX timestamp)
+}
+
+pub fn read_history_file<P: AsRef<Path>>(history_file_path: P) -> Result<Vec<PatchHistoryEntry>> {
+    let file = File::open(history_file_path)?;
+    serde_json::from_reader(file).context("Failed to deserialize patch history")
+}
+
+/// Appends an entry to the history file, only keeping the `max_entries` most
+/// recent entries.
+pub fn append_history_entry<P: AsRef<Path>>(
+    history_file_path: P,
+    entry: PatchHistoryEntry,
+    max_entries: usize,
+) -> Result<()> {
+    // Note: Start over if the history file is missing or invalid
+    let mut history = read_history_file(&history_file_path).unwrap_or_default();
+    history.push(entry);
+    if history.len() > max_entries {
+        let excess = history.len() - max_entries;
+        history.drain(..excess);
+    }
+    let file = File::create(history_file_path)?;
+    serde_json::to_writer(file, &history).context("Failed to serialize patch history")
+}
+
+/// Validator of the last patch list that was fully applied, which allows
+/// skipping patching when the patch list hasn't changed since then.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct PatchListValidator {
+    pub plist_url: String,               // URL the patch list was downloaded from
+    pub etag: String,                    // Entity tag of the patch list
+    pub last_patch_index: Option<usize>, // Index of the last applied patch at the time
+}
+
+pub fn read_plist_validator_file<P: AsRef<Path>>(
+    plist_validator_file_path: P,
+) -> Result<PatchListValidator> {
+    let file = File::open(plist_validator_file_path)?;
+    serde_json::from_reader(file).context("Failed to deserialize patch list validator")
+}
+
+pub fn write_plist_validator_file<P: AsRef<Path>>(
+    plist_validator_file_path: P,
+    validator: &PatchListValidator,
+) -> Result<()> {
+    let file = File::create(plist_validator_file_path)?;
+    serde_json::to_writer(file, validator).context("Failed to serialize patch list validator")
+}
+
+/// Checksums of the files checked by the tamper check, as contained in the
+/// last applied patch, so that the patch doesn't have to be downloaded again.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct PatchChecksums {
+    pub patch_index: usize,              // Index of the patch
+    pub patch_name: String,              // File name of the patch
+    pub target_grf_name: Option<String>, // GRF the patch was applied to (if any)
+    pub checksums: HashMap<String, u32>, // Checksums of the files, by path
+}
+
+pub fn read_checksums_file<P: AsRef<Path>>(checksums_file_path: P) -> Result<PatchChecksums> {
+    let file = File::open(checksums_file_path)?;
+    serde_json::from_reader(file).context("Failed to deserialize patch checksums")
+}
+
+pub fn write_checksums_file<P: AsRef<Path>>(
+    checksums_file_path: P,
+    patch_checksums: &PatchChecksums,
+) -> Result<()> {
+    let file = File::create(checksums_file_path)?;
+    serde_json::to_writer(file, patch_checksums).context("Failed to serialize patch checksums")
+}
+
 pub async fn write_cache_file<P: AsRef<Path>>(
     cache_file_path: P,
     new_cache: PatcherCache,
@@ -21,3 +101,201 @@ pub async fn write_cache_file<P: AsRef<Path>>(
     let file = File::create(cache_file_path)?;
     Ok(bincode::serialize_into(file, &new_cache).context("Failed to serialize patcher cache")?)
 }
+
+/// Name of the file listing the patches downloaded to a cache directory, so
+/// that files that don't belong to the cache are never removed from it.
+const CACHE_MANIFEST_FILE_NAME: &str = "rpatchur.cache.json";
+/// Extension of the files that are being downloaded.
+const PARTIAL_FILE_EXTENSION: &str = ".partial";
+
+/// Records that a patch has been downloaded to a cache directory.
+pub fn register_cached_file<P: AsRef<Path>>(cache_directory: P, file_name: &str) -> Result<()> {
+    let mut manifest = read_cache_manifest(cache_directory.as_ref());
+    if manifest.insert(file_name.to_string()) {
+        write_cache_manifest(cache_directory.as_ref(), &manifest)?;
+    }
+    Ok(())
+}
+
+/// Removes the oldest files of a cache directory until its total size goes
+/// below `max_size` (in bytes).
+pub fn trim_cache_directory<P: AsRef<Path>>(cache_directory: P, max_size: u64) -> Result<()> {
+    let cache_directory = cache_directory.as_ref();
+    let mut cached_files = list_cached_files(cache_directory)?;
+    let mut cache_size: u64 = cached_files.iter().map(|(_, size, _)| size).sum();
+    // Oldest files first
+    cached_files.sort_unstable_by_key(|(_, _, modified)| *modified);
+    let mut manifest = read_cache_manifest(cache_directory);
+    for (file_name, file_size, _) in cached_files {
+        if cache_size <= max_size {
+            break;
+        }
+        let file_path = cache_directory.join(&file_name);
+        log::info!("Removing {} from the download cache", file_path.display());
+        fs::remove_file(&file_path)
+            .context(format!("Failed to remove '{}'", file_path.display()))?;
+        manifest.remove(&file_name);
+        cache_size -= file_size;
+    }
+    write_cache_manifest(cache_directory, &manifest)
+}
+
+/// Removes all the files of a cache directory.
+pub fn clear_cache_directory<P: AsRef<Path>>(cache_directory: P) -> Result<()> {
+    let cache_directory = cache_directory.as_ref();
+    for (file_name, _, _) in list_cached_files(cache_directory)? {
+        let file_path = cache_directory.join(file_name);
+        fs::remove_file(&file_path)
+            .context(format!("Failed to remove '{}'", file_path.display()))?;
+    }
+    let manifest_file_path = cache_directory.join(CACHE_MANIFEST_FILE_NAME);
+    if manifest_file_path.exists() {
+        fs::remove_file(&manifest_file_path).context("Failed to remove cache manifest")?;
+    }
+    Ok(())
+}
+
+/// Returns the name, size and modification time of the files that belong to a
+/// cache directory (i.e., registered patches and partial downloads).
+///
+/// Working directories are refused, as they contain the game client's files.
+fn list_cached_files(cache_directory: &Path) -> Result<Vec<(String, u64, SystemTime)>> {
+    let mut cached_files = vec![];
+    if !cache_directory.exists() {
+        return Ok(cached_files);
+    }
+    if cache_directory.canonicalize()? == std::env::current_dir()?.canonicalize()? {
+        return Err(anyhow!(
+            "Refusing to use the working directory as a cache directory"
+        ));
+    }
+    let mut file_names = read_cache_manifest(cache_directory);
+    for entry in fs::read_dir(cache_directory)? {
+        let file_name = entry?.file_name().to_string_lossy().into_owned();
+        if file_name.ends_with(PARTIAL_FILE_EXTENSION) {
+            file_names.insert(file_name);
+        }
+    }
+    for file_name in file_names {
+        // Note: Registered files might have been removed manually
+        let metadata = match fs::metadata(cache_directory.join(&file_name)) {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+        if metadata.is_file() {
+            cached_files.push((file_name, metadata.len(), metadata.modified()?));
+        }
+    }
+    Ok(cached_files)
+}
+
+/// Returns the names of the patches registered in a cache directory.
+fn read_cache_manifest(cache_directory: &Path) -> BTreeSet<String> {
+    // Note: Start over if the manifest is missing or invalid
+    File::open(cache_directory.join(CACHE_MANIFEST_FILE_NAME))
+        .ok()
+        .and_then(|file| serde_json::from_reader(file).ok())
+        .unwrap_or_default()
+}
+
+fn write_cache_manifest(cache_directory: &Path, manifest: &BTreeSet<String>) -> Result<()> {
+    let file = File::create(cache_directory.join(CACHE_MANIFEST_FILE_NAME))?;
+    serde_json::to_writer(file, manifest).context("Failed to serialize cache manifest")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_trim_cache_directory() {
+        let cache_dir = tempdir().unwrap();
+        for (i, file_name) in ["1.thor", "2.thor", "3.thor"].iter().enumerate() {
+            fs::write(cache_dir.path().join(file_name), vec![0; 100]).unwrap();
+            register_cached_file(cache_dir.path(), file_name).unwrap();
+            if i < 2 {
+                // Make sure modification times differ
+                std::thread::sleep(Duration::from_millis(20));
+            }
+        }
+        trim_cache_directory(cache_dir.path(), 250).unwrap();
+        assert!(!cache_dir.path().join("1.thor").exists());
+        assert!(cache_dir.path().join("2.thor").exists());
+        assert!(cache_dir.path().join("3.thor").exists());
+
+        // Files that don't belong to the cache are left untouched
+        fs::write(cache_dir.path().join("4.thor.partial"), vec![0; 100]).unwrap();
+        fs::write(cache_dir.path().join("game.exe"), vec![0; 1000]).unwrap();
+        trim_cache_directory(cache_dir.path(), 0).unwrap();
+        assert!(!cache_dir.path().join("2.thor").exists());
+        assert!(!cache_dir.path().join("4.thor.partial").exists());
+        assert!(cache_dir.path().join("game.exe").exists());
+
+        register_cached_file(cache_dir.path(), "1.thor").unwrap();
+        fs::write(cache_dir.path().join("1.thor"), vec![0; 100]).unwrap();
+        clear_cache_directory(cache_dir.path()).unwrap();
+        assert_eq!(1, fs::read_dir(cache_dir.path()).unwrap().count());
+        assert!(cache_dir.path().join("game.exe").exists());
+
+        assert!(clear_cache_directory(std::env::current_dir().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_append_history_entry() {
+        let temp_dir = tempdir().unwrap();
+        let history_file_path = temp_dir.path().join("rpatchur.history.json");
+        assert!(read_history_file(&history_file_path).is_err());
+        for index in 1..=4 {
+            let entry = PatchHistoryEntry {
+                index,
+                file_name: format!("{}.thor", index),
+                applied_at: 1_600_000_000 + index as u64,
+            };
+            append_history_entry(&history_file_path, entry, 3).unwrap();
+        }
+        let history = read_history_file(&history_file_path).unwrap();
+        let indices: Vec<usize> = history.iter().map(|e| e.index).collect();
+        assert_eq!(indices, vec![2, 3, 4]);
+        assert_eq!(history[2].file_name, "4.thor");
+        assert_eq!(history[2].applied_at, 1_600_000_004);
+    }
+
+    #[test]
+    fn test_plist_validator_file() {
+        let temp_dir = tempdir().unwrap();
+        let plist_validator_file_path = temp_dir.path().join("rpatchur.plist.json");
+        assert!(read_plist_validator_file(&plist_validator_file_path).is_err());
+        let validator = PatchListValidator {
+            plist_url: "https://myserver.com/plist.txt".to_string(),
+            etag: "\"5f3a-1b\"".to_string(),
+            last_patch_index: Some(42),
+        };
+        write_plist_validator_file(&plist_validator_file_path, &validator).unwrap();
+        assert_eq!(
+            read_plist_validator_file(&plist_validator_file_path).unwrap(),
+            validator
+        );
+    }
+
+    #[test]
+    fn test_checksums_file() {
+        let temp_dir = tempdir().unwrap();
+        let checksums_file_path = temp_dir.path().join("rpatchur.checksums.json");
+        assert!(read_checksums_file(&checksums_file_path).is_err());
+        let patch_checksums = PatchChecksums {
+            patch_index: 42,
+            patch_name: "2020-12-20_data.thor".to_string(),
+            target_grf_name: Some("myserver.grf".to_string()),
+            checksums: vec![("data\\clientinfo.xml".to_string(), 0xDEAD_BEEF)]
+                .into_iter()
+                .collect(),
+        };
+        write_checksums_file(&checksums_file_path, &patch_checksums).unwrap();
+        assert_eq!(
+            read_checksums_file(&checksums_file_path).unwrap(),
+            patch_checksums
+        );
+    }
+}
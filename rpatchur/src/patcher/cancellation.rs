@@ -1,23 +1,39 @@
+use std::fmt;
+
 use super::PatcherCommand;
+use anyhow::anyhow;
 use tokio::sync::mpsc;
 
 pub type InterruptibleFnResult<T> = std::result::Result<T, InterruptibleFnError>;
 
 pub enum InterruptibleFnError {
-    Err(String), // An actual error
-    Interrupted, // An interruption
+    Err(anyhow::Error), // An actual error
+    Interrupted,        // An interruption
+}
+
+/// Error indicating that a task has been canceled by the user, as opposed to
+/// an actual failure.
+#[derive(Debug)]
+pub struct CanceledError;
+
+impl fmt::Display for CanceledError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Canceled by the user")
+    }
 }
 
+impl std::error::Error for CanceledError {}
+
 pub async fn wait_for_cancellation(
     patching_thread_rx: &mut mpsc::Receiver<PatcherCommand>,
 ) -> InterruptibleFnError {
     if let Some(cmd) = patching_thread_rx.recv().await {
         match cmd {
             PatcherCommand::Cancel => InterruptibleFnError::Interrupted,
-            _ => InterruptibleFnError::Err("Unexpected command received".to_string()),
+            _ => InterruptibleFnError::Err(anyhow!("Unexpected command received")),
         }
     } else {
-        InterruptibleFnError::Err("Channel was closed".to_string())
+        InterruptibleFnError::Err(anyhow!("Channel was closed"))
     }
 }
 
@@ -0,0 +1,105 @@
+use std::io::{self, Write};
+
+use anyhow::{anyhow, Result};
+use flate2::write::{GzDecoder, ZlibDecoder};
+use reqwest::header::{self, HeaderMap};
+
+/// Value of the 'Accept-Encoding' header sent with download requests.
+pub const ACCEPTED_CONTENT_ENCODINGS: &str = "gzip, deflate";
+
+/// Incremental decoder of response bodies compressed with the 'gzip' or
+/// 'deflate' content encodings.
+pub enum ContentDecoder {
+    Gzip(GzDecoder<Vec<u8>>),
+    Deflate(ZlibDecoder<Vec<u8>>),
+}
+
+impl ContentDecoder {
+    /// Returns a decoder for the content encoding indicated in `headers`, or
+    /// `None` if the content isn't encoded.
+    pub fn from_headers(headers: &HeaderMap) -> Result<Option<Self>> {
+        let content_encoding = match headers.get(header::CONTENT_ENCODING) {
+            Some(v) => v
+                .to_str()
+                .map_err(|_| anyhow!("Invalid content encoding"))?
+                .trim()
+                .to_lowercase(),
+            None => return Ok(None),
+        };
+        match content_encoding.as_str() {
+            "" | "identity" => Ok(None),
+            "gzip" | "x-gzip" => Ok(Some(Self::Gzip(GzDecoder::new(Vec::new())))),
+            "deflate" => Ok(Some(Self::Deflate(ZlibDecoder::new(Vec::new())))),
+            _ => Err(anyhow!(
+                "Unsupported content encoding '{}'",
+                content_encoding
+            )),
+        }
+    }
+
+    /// Decodes a chunk of the response body and returns the content decoded
+    /// so far.
+    pub fn decode(&mut self, chunk: &[u8]) -> io::Result<Vec<u8>> {
+        let decoded = match self {
+            Self::Gzip(decoder) => {
+                decoder.write_all(chunk)?;
+                decoder.get_mut()
+            }
+            Self::Deflate(decoder) => {
+                decoder.write_all(chunk)?;
+                decoder.get_mut()
+            }
+        };
+        Ok(std::mem::take(decoded))
+    }
+
+    /// Returns the rest of the decoded content, once the whole body has been
+    /// decoded.
+    pub fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            Self::Gzip(decoder) => decoder.finish(),
+            Self::Deflate(decoder) => decoder.finish(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::{GzEncoder, ZlibEncoder};
+    use flate2::Compression;
+    use reqwest::header::HeaderValue;
+
+    fn decode(content_encoding: &str, body: &[u8]) -> Vec<u8> {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::CONTENT_ENCODING,
+            HeaderValue::from_str(content_encoding).unwrap(),
+        );
+        let mut decoder = ContentDecoder::from_headers(&headers).unwrap().unwrap();
+        let mut content = vec![];
+        for chunk in body.chunks(7) {
+            content.extend(decoder.decode(chunk).unwrap());
+        }
+        content.extend(decoder.finish().unwrap());
+        content
+    }
+
+    #[test]
+    fn test_content_decoder() {
+        let content: Vec<u8> = (0..10000).map(|x| (x % 251) as u8).collect();
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&content).unwrap();
+        assert_eq!(decode("gzip", &encoder.finish().unwrap()), content);
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&content).unwrap();
+        assert_eq!(decode("Deflate", &encoder.finish().unwrap()), content);
+
+        assert!(ContentDecoder::from_headers(&HeaderMap::new())
+            .unwrap()
+            .is_none());
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static("br"));
+        assert!(ContentDecoder::from_headers(&headers).is_err());
+    }
+}
@@ -1,25 +1,210 @@
+use std::collections::HashMap;
 use std::env;
+use std::fmt;
+use std::future::Future;
+use std::io::{Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use super::cache::{read_cache_file, write_cache_file, PatcherCache};
+use super::cache::{
+    append_history_entry, clear_cache_directory, read_cache_file, read_checksums_file,
+    read_plist_validator_file, register_cached_file, trim_cache_directory, write_cache_file,
+    write_checksums_file, write_plist_validator_file, PatchChecksums, PatchHistoryEntry,
+    PatchListValidator, PatcherCache,
+};
 use super::cancellation::{
-    check_for_cancellation, wait_for_cancellation, InterruptibleFnError, InterruptibleFnResult,
+    check_for_cancellation, wait_for_cancellation, CanceledError, InterruptibleFnError,
+    InterruptibleFnResult,
+};
+use super::config::{
+    CommandConfiguration, GrfMergeStrategy, PatchListFormat, SelfUpdateConfiguration,
+};
+use super::content_encoding::{ContentDecoder, ACCEPTED_CONTENT_ENCODINGS};
+use super::error::{
+    AccessDeniedError, ChecksumError, FileErrorContext, HttpStatusError,
+    InsufficientDiskSpaceError, PatchingError,
+};
+use super::http_headers::default_headers;
+use super::metrics::DownloadMetrics;
+use super::partial_file::{partial_file_path, remove_stale_partial_files};
+use super::patching::{
+    apply_patch_to_disk, apply_patch_to_grf, apply_rgz_patch_to_disk, detect_patch_archive_format,
+    disk_matches_patch, grf_matches_patch, join_windows_relative_path, open_thor_archive,
+    simulate_patch, simulate_rgz_patch, GrfPatchingMethod, PatchArchiveFormat, DEFAULT_GRF_VERSION,
+};
+use super::pinning::{is_pin_mismatch, pinned_tls_config, CertificatePins};
+use super::plist::parse_patch_list;
+use super::self_update::{cleanup_previous_update, update_executable};
+use super::signature::{fetch_signature, SignatureVerifier};
+use super::signed_url::SignedUrlBuilder;
+use super::tamper::{find_tampered_files, send_tamper_report, TamperReport, TamperedFile};
+use super::throttle::BandwidthThrottle;
+use super::{
+    get_cache_file_path, get_checksums_file_path, get_history_file_path,
+    get_plist_validator_file_path, PatcherCommand, PatcherConfiguration,
 };
-use super::patching::{apply_patch_to_disk, apply_patch_to_grf, GrfPatchingMethod};
-use super::{get_patcher_name, PatcherCommand, PatcherConfiguration};
 use crate::ui::{PatchingStatus, UIController};
 use anyhow::{anyhow, Context, Result};
 use futures::executor::block_on;
 use futures::stream::{StreamExt, TryStreamExt};
+use gruf::grf::GrfArchive;
+use gruf::rgz::RgzArchive;
 use gruf::thor::{self, ThorArchive, ThorPatchInfo, ThorPatchList};
 use gruf::GrufError;
-use tokio::fs::File;
+use reqwest::{header, StatusCode};
+use tokio::fs::{self, File, OpenOptions};
 use tokio::io::AsyncWriteExt;
 use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+use tokio::time;
 use url::Url;
 
+const DEFAULT_CONCURRENT_DOWNLOADS: usize = 32;
+const DEFAULT_MAX_RETRIES: usize = 3;
+const DEFAULT_RETRY_BASE_DELAY_MS: u64 = 500;
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+const MAX_HISTORY_ENTRIES: usize = 1000;
+const DEFAULT_CACHE_DIRECTORY: &str = "patches";
+/// Age from which partial files are considered abandoned (7 days).
+const STALE_PARTIAL_FILE_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Remote location from which the patch list and the patches can be downloaded.
+#[derive(Debug)]
+struct Mirror {
+    plist_url: Url,
+    patch_url: Url,
+    signed_urls: Option<SignedUrlBuilder>, // Builder of patch URLs (replaces `patch_url`)
+}
+
+impl Mirror {
+    /// Returns the URL of `patch` on this mirror, along with the token it
+    /// contains if it's built from a template.
+    async fn patch_file_url(
+        &self,
+        client: &reqwest::Client,
+        patch: &ThorPatchInfo,
+    ) -> Result<(Url, Option<String>)> {
+        match &self.signed_urls {
+            Some(signed_urls) => {
+                let (url, token) = signed_urls.url(client, &patch.file_name).await?;
+                Ok((url, Some(token)))
+            }
+            None => {
+                let url = self
+                    .patch_url
+                    .join(patch.file_name.as_str())
+                    .context(format!(
+                        "Invalid file name '{}' given in patch list file",
+                        patch.file_name
+                    ))?;
+                Ok((url, None))
+            }
+        }
+    }
+}
+
+/// Error indicating that the server didn't send anything for longer than the
+/// configured read timeout.
+#[derive(Debug)]
+struct ReadTimeoutError;
+
+impl fmt::Display for ReadTimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Timed out while waiting for the server")
+    }
+}
+
+impl std::error::Error for ReadTimeoutError {}
+
+/// Directory in which patches are downloaded.
+///
+/// Temporary directories are removed when dropped, along with the files they
+/// contain.
+enum DownloadDirectory {
+    Temporary(tempfile::TempDir),
+    Cache(PathBuf),
+}
+
+impl DownloadDirectory {
+    fn new(config: &PatcherConfiguration) -> Result<Self> {
+        match download_cache_directory(config) {
+            Some(cache_directory) => {
+                std::fs::create_dir_all(&cache_directory)
+                    .context("Failed to create cache directory")?;
+                Ok(Self::Cache(cache_directory))
+            }
+            None => Ok(Self::Temporary(
+                tempfile::tempdir().context("Failed to create temporary directory")?,
+            )),
+        }
+    }
+
+    fn path(&self) -> &Path {
+        match self {
+            Self::Temporary(tmp_dir) => tmp_dir.path(),
+            Self::Cache(path) => path,
+        }
+    }
+}
+
+/// Returns the directory in which downloaded patches are kept, if any.
+///
+/// Patches are kept in `DEFAULT_CACHE_DIRECTORY` when asked to keep them
+/// without specifying a cache directory.
+fn download_cache_directory(config: &PatcherConfiguration) -> Option<PathBuf> {
+    match &config.patching.cache_directory {
+        Some(cache_directory) => Some(PathBuf::from(cache_directory)),
+        None if keep_patches(config) => Some(PathBuf::from(DEFAULT_CACHE_DIRECTORY)),
+        None => None,
+    }
+}
+
+/// Indicates whether downloaded patches should be kept after being applied.
+///
+/// Patches are only kept when a cache directory is configured by default.
+fn keep_patches(config: &PatcherConfiguration) -> bool {
+    config
+        .patching
+        .keep_patches
+        .unwrap_or_else(|| config.patching.cache_directory.is_some())
+}
+
+/// Removes the partial files left over by interrupted runs (e.g., after a
+/// crash), from the game client's directory and the download cache.
+fn remove_stale_files(config: &PatcherConfiguration) {
+    // Note: GRFs are patched relatively to the game client's directory
+    let mut directories = vec![PathBuf::from(".")];
+    directories.extend(download_cache_directory(config));
+    for directory in directories {
+        if let Err(e) = remove_stale_partial_files(&directory, STALE_PARTIAL_FILE_AGE) {
+            log::warn!(
+                "Failed to remove stale files from '{}': {}",
+                directory.display(),
+                e
+            );
+        }
+    }
+}
+
+/// Removes a patch that's been applied successfully, unless configured
+/// otherwise.
+fn discard_patch_file(config: &PatcherConfiguration, patch_file_path: &Path) {
+    if keep_patches(config) {
+        return;
+    }
+    if let Err(e) = std::fs::remove_file(patch_file_path) {
+        log::warn!("Failed to remove '{}': {}.", patch_file_path.display(), e);
+    }
+}
+
+/// Patch list downloaded from one of the mirrors.
+struct RemotePatchList {
+    patch_list: ThorPatchList,
+    plist_url: Url,       // URL the patch list was downloaded from
+    etag: Option<String>, // Entity tag of the patch list, if any
+}
+
 /// Representation of a pending patch (a patch that's been downloaded but has
 /// not been applied yet).
 #[derive(Debug)]
@@ -28,110 +213,393 @@ struct PendingPatch {
     local_file_path: PathBuf,
 }
 
+/// Runs the tasks performed whenever the patcher starts, with or without the
+/// UI: stale files are removed and the patcher is updated in the background
+/// (if enabled).
+///
+/// Returns the handle of the self-update task, if any.
+pub fn startup_routine(config: &PatcherConfiguration) -> Option<JoinHandle<()>> {
+    remove_stale_files(config);
+    let self_update_config = config.self_update.clone()?;
+    if !self_update_config.enabled {
+        return None;
+    }
+    let config = config.clone();
+    Some(tokio::spawn(async move {
+        if let Err(e) = self_update_routine(&config, &self_update_config).await {
+            log::warn!("Failed to update the patcher: {:#}", e);
+        }
+    }))
+}
+
 /// Entry point of the patching task.
 ///
-/// This waits for a `PatcherCommand::Start` command before starting an
-/// interruptible patching task.
+/// This waits for a `PatcherCommand::Start`, `PatcherCommand::ForceStart`,
+/// `PatcherCommand::VerifyIntegrity`,
+/// `PatcherCommand::QueryPendingPatches`, `PatcherCommand::ClearCache` or
+/// `PatcherCommand::ResetPatchIndex` command before starting the corresponding
+/// task, until the other end of the channel is closed.
 pub async fn patcher_thread_routine(
     ui_controller: UIController,
     config: PatcherConfiguration,
     mut patcher_thread_rx: mpsc::Receiver<PatcherCommand>,
 ) {
     log::trace!("Patching thread started.");
-    log::trace!("Waiting for start command");
-    if let Err(e) = wait_for_start_command(&mut patcher_thread_rx).await {
-        log::error!("Failed to wait for start command: {}", e);
-        return;
+    startup_routine(&config);
+    loop {
+        log::trace!("Waiting for start command");
+        let cmd = match wait_for_start_command(&mut patcher_thread_rx).await {
+            Err(e) => {
+                log::trace!("Patching thread stopped: {}", e);
+                return;
+            }
+            Ok(v) => v,
+        };
+
+        let result = match cmd {
+            PatcherCommand::ClearCache => clear_download_cache(&config),
+            PatcherCommand::ResetPatchIndex(patch_index) => {
+                reset_patch_index(&ui_controller, &config, patch_index).await
+            }
+            PatcherCommand::QueryPendingPatches => {
+                query_pending_patches(&ui_controller, &config).await
+            }
+            PatcherCommand::VerifyIntegrity => {
+                interruptible_repair_routine(&ui_controller, &config, &mut patcher_thread_rx).await
+            }
+            PatcherCommand::ForceStart => {
+                let mut config = config.clone();
+                config.web.skip_unchanged_plist = Some(false);
+                interruptible_patcher_routine(&ui_controller, &config, &mut patcher_thread_rx).await
+            }
+            _ => {
+                interruptible_patcher_routine(&ui_controller, &config, &mut patcher_thread_rx).await
+            }
+        };
+        if let Err(err) = result {
+            if err.downcast_ref::<CanceledError>().is_some() {
+                // Note: Partially downloaded patches are either removed along
+                // with the temporary directory or resumed later (when using a
+                // cache directory), and the cache file only ever contains the
+                // index of the last applied patch
+                log::info!("{:#}", err);
+                ui_controller
+                    .dispatch_patching_status(PatchingStatus::Canceled)
+                    .await;
+                continue;
+            }
+            log::error!("{:#}", err);
+            ui_controller
+                .dispatch_patching_status(PatchingStatus::Error(PatchingError::new(&err)))
+                .await;
+        }
+    }
+}
+
+/// Removes all the patches from the download cache (if any).
+///
+/// Note: This is only done between tasks, so that patches that haven't been
+/// applied yet are never removed.
+fn clear_download_cache(config: &PatcherConfiguration) -> Result<()> {
+    if let Some(cache_directory) = download_cache_directory(config) {
+        clear_cache_directory(cache_directory).context("Failed to clear the download cache")?;
+        log::info!("Download cache cleared");
     }
+    Ok(())
+}
 
-    if let Err(err) = interruptible_patcher_routine(&ui_controller, config, patcher_thread_rx).await
-    {
-        log::error!("{:#}", err);
-        ui_controller
-            .dispatch_patching_status(PatchingStatus::Error(format!("{:#}", err)))
-            .await;
+/// Rewrites the cache file so that the patches that follow `patch_index` are
+/// applied again on the next run (all the patches if `patch_index` is `None`).
+///
+/// Indices greater than the index of the latest patch are refused. The index
+/// that's actually stored is sent back to the UI.
+async fn reset_patch_index(
+    ui_controller: &UIController,
+    config: &PatcherConfiguration,
+    patch_index: Option<usize>,
+) -> Result<()> {
+    let mirrors = mirror_list(config)?;
+    let client = build_http_client(config)?;
+    let patch_list =
+        fetch_patch_list_from_mirrors(&client, &mirrors, config, ui_controller).await?;
+    let last_patch_index = resolve_patch_index(&patch_list, patch_index)?;
+    let cache_file_path = get_cache_file_path(config).context("Failed to resolve patcher name")?;
+    match last_patch_index {
+        Some(last_patch_index) => {
+            write_cache_file(&cache_file_path, PatcherCache { last_patch_index })
+                .await
+                .context("Failed to write cache file")?
+        }
+        None => {
+            if cache_file_path.exists() {
+                std::fs::remove_file(&cache_file_path).context("Failed to remove cache file")?;
+            }
+        }
+    }
+    log::info!("Patch index reset to {:?}", last_patch_index);
+    ui_controller
+        .dispatch_patching_status(PatchingStatus::PatchIndexReset(last_patch_index))
+        .await;
+    Ok(())
+}
+
+/// Returns the index to store in the cache file so that the patches that
+/// follow `patch_index` are considered pending.
+///
+/// Fails if `patch_index` is greater than the index of the latest patch.
+fn resolve_patch_index(
+    patch_list: &[ThorPatchInfo],
+    patch_index: Option<usize>,
+) -> Result<Option<usize>> {
+    let patch_index = match patch_index {
+        None => return Ok(None),
+        Some(v) => v,
+    };
+    let latest_patch_index = patch_list.iter().map(|p| p.index).max();
+    match latest_patch_index {
+        Some(latest_patch_index) if patch_index <= latest_patch_index => {}
+        _ => {
+            return Err(anyhow!(
+                "Cannot reset the patch index to {}, the latest patch's index is {}",
+                patch_index,
+                latest_patch_index.map_or("unknown".to_string(), |i| i.to_string())
+            ))
+        }
     }
+    // Note: The cache file is ignored if its index isn't part of the patch
+    // list in strict mode, so use the closest preceding patch
+    Ok(patch_list
+        .iter()
+        .map(|p| p.index)
+        .filter(|index| *index <= patch_index)
+        .max())
+}
+
+/// Replaces the patcher's executable with a newer version if available.
+async fn self_update_routine(
+    config: &PatcherConfiguration,
+    self_update_config: &SelfUpdateConfiguration,
+) -> Result<()> {
+    let current_exe_path = env::current_exe().context("Failed to resolve executable path")?;
+    cleanup_previous_update(&current_exe_path);
+    let verifier = signature_verifier(config)?
+        .ok_or_else(|| anyhow!("Self-updates require a 'public_key' to verify updates"))?;
+    let client = build_http_client(config)?;
+    update_executable(&client, self_update_config, &verifier, &current_exe_path).await?;
+    Ok(())
 }
 
-/// Returns when a start command is received, ignoring all other commands that might be received.
+/// Returns when a command that starts a task is received, ignoring all other
+/// commands that might be received.
 /// Returns an error if the other end of the channel happens to be closed while waiting.
-async fn wait_for_start_command(rx: &mut mpsc::Receiver<PatcherCommand>) -> Result<()> {
+async fn wait_for_start_command(rx: &mut mpsc::Receiver<PatcherCommand>) -> Result<PatcherCommand> {
     loop {
         let cmd = rx
             .recv()
             .await
             .ok_or_else(|| anyhow!("Channel has been closed"))?;
-        if let PatcherCommand::Start = cmd {
-            break;
+        match cmd {
+            PatcherCommand::Start
+            | PatcherCommand::ForceStart
+            | PatcherCommand::VerifyIntegrity
+            | PatcherCommand::QueryPendingPatches
+            | PatcherCommand::ClearCache
+            | PatcherCommand::ResetPatchIndex(_) => return Ok(cmd),
+            _ => continue,
         }
     }
-    Ok(())
 }
 
 /// Main routine of the patching task.
 ///
 /// This routine is written in a way that makes it interuptible (or cancellable)
 /// with a relatively low latency.
-async fn interruptible_patcher_routine(
+pub async fn interruptible_patcher_routine(
     ui_controller: &UIController,
-    config: PatcherConfiguration,
-    mut patcher_thread_rx: mpsc::Receiver<PatcherCommand>,
+    config: &PatcherConfiguration,
+    patcher_thread_rx: &mut mpsc::Receiver<PatcherCommand>,
 ) -> Result<()> {
     log::info!("Patching started");
-    let patch_list_url = Url::parse(config.web.plist_url.as_str())?;
-    let mut patch_list = fetch_patch_list(patch_list_url)
-        .await
-        .context("Failed to retrieve the patch list")?;
+    let mirrors = mirror_list(config)?;
+    let client = build_http_client(config)?;
+    let cache_file_path = get_cache_file_path(config).context("Failed to resolve patcher name")?;
+    let plist_validator_file_path =
+        get_plist_validator_file_path(config).context("Failed to resolve patcher name")?;
+    // Note: Patching is skipped when the patch list hasn't changed since it
+    // was last applied, unless only part of it is processed
+    let skip_unchanged_plist = config.web.skip_unchanged_plist.unwrap_or(false)
+        && config.patching.index_range.is_none()
+        && !config.patching.simulate.unwrap_or(false);
+    let plist_validator = if skip_unchanged_plist {
+        current_plist_validator(&plist_validator_file_path, &cache_file_path).await
+    } else {
+        None
+    };
+    let remote_patch_list = match fetch_patch_list_from_mirrors_if_modified(
+        &client,
+        &mirrors,
+        config,
+        ui_controller,
+        plist_validator.as_ref(),
+    )
+    .await?
+    {
+        Some(v) => v,
+        None => {
+            log::info!("Patch list hasn't changed, nothing to patch");
+            ui_controller
+                .dispatch_patching_status(PatchingStatus::Ready)
+                .await;
+            return Ok(());
+        }
+    };
+    let mut patch_list = remote_patch_list.patch_list;
     log::info!("Successfully fetched patch list: {:?}", patch_list);
 
     // Try to read cache
-    let cache_file_path = get_cache_file_path().context("Failed to resolve patcher name")?;
-    if let Ok(patcher_cache) = read_cache_file(&cache_file_path).await {
-        // Ignore already applied patches if needed
-        // First we verify that our cached index looks relevant
-        let should_filter_patch_list = patch_list
-            .iter()
-            .any(|x| x.index == patcher_cache.last_patch_index);
-        if should_filter_patch_list {
-            patch_list.retain(|x| x.index > patcher_cache.last_patch_index);
+    let mut applied_patch_list = filter_applied_patches(
+        &mut patch_list,
+        &cache_file_path,
+        config.patching.strict_plist_sequence.unwrap_or(false),
+    )
+    .await;
+    if let Some(index_range) = &config.patching.index_range {
+        patch_list.retain(|patch| index_range.contains(patch.index));
+    }
+    let download_directory = DownloadDirectory::new(config)?;
+
+    // Note: The game client is checked before applied patches are verified,
+    // which would restore tampered files
+    if config.patching.tamper_check.is_some() {
+        if let Some(latest_patch) = applied_patch_list.last() {
+            check_for_tampering(&client, latest_patch.index, config).await;
         }
-    };
+    }
+
+    // Note: Applied patches are verified before applying new ones, so that
+    // the files they contain are up to date in the end
+    let verified_patch_count = config.patching.verify_applied.unwrap_or(0);
+    if verified_patch_count > 0 && !config.patching.simulate.unwrap_or(false) {
+        let first_verified_patch = applied_patch_list
+            .len()
+            .saturating_sub(verified_patch_count);
+        verify_applied_patches(
+            &client,
+            &mirrors,
+            applied_patch_list.split_off(first_verified_patch),
+            download_directory.path(),
+            config,
+            ui_controller,
+            patcher_thread_rx,
+        )
+        .await?;
+    }
+
+    // Note: The total size is shared by the disk space check and the download
+    // metrics
+    let patch_batch = PatchBatch::new(&client, &mirrors, patch_list).await;
+    // Note: Checking the available disk space can be disabled for file systems
+    // that don't report it properly. It's also skipped if the size of the
+    // patches cannot be retrieved.
+    if config.patching.check_disk_space.unwrap_or(true)
+        && !config.patching.simulate.unwrap_or(false)
+        && !patch_batch.patch_list.is_empty()
+    {
+        if let Some(total_patch_size) = patch_batch.total_size {
+            check_disk_space(total_patch_size, config)?;
+        }
+    }
 
     // Try fetching patch files
     log::info!("Downloading patches... ");
-    let patch_url =
-        Url::parse(config.web.patch_url.as_str()).context("Failed to parse 'patch_url'")?;
-    let tmp_dir = tempfile::tempdir().context("Failed to create temporary directory")?;
     let pending_patch_queue = download_patches_concurrent(
-        patch_url,
-        patch_list,
-        tmp_dir.path(),
-        config.patching.check_integrity,
-        &ui_controller,
-        &mut patcher_thread_rx,
+        &client,
+        &mirrors,
+        patch_batch,
+        download_directory.path(),
+        config,
+        ui_controller,
+        patcher_thread_rx,
     )
     .await
     .map_err(|e| match e {
-        InterruptibleFnError::Err(msg) => anyhow!("Failed to download patches: {}", msg),
-        InterruptibleFnError::Interrupted => anyhow!("Patching was canceled"),
+        InterruptibleFnError::Err(e) => e.context("Failed to download patches"),
+        InterruptibleFnError::Interrupted => {
+            anyhow::Error::new(CanceledError).context("Patching was canceled")
+        }
     })?;
     log::info!("Done");
 
+    if config.patching.simulate.unwrap_or(false) {
+        // Only report what would be applied, GRFs are left untouched
+        log::info!("Simulating patches...");
+        simulate_patches(
+            pending_patch_queue,
+            config,
+            ui_controller,
+            patcher_thread_rx,
+        )
+        .await
+        .map_err(|e| match e {
+            InterruptibleFnError::Err(e) => e.context("Failed to simulate patches"),
+            InterruptibleFnError::Interrupted => {
+                anyhow::Error::new(CanceledError).context("Patching was canceled")
+            }
+        })?;
+        log::info!("Done");
+        trim_download_cache(config);
+        ui_controller
+            .dispatch_patching_status(PatchingStatus::Ready)
+            .await;
+        log::info!("Simulation finished!");
+        return Ok(());
+    }
+
+    // Note: Hooks are only run when there's something to patch
+    let has_pending_patches = !pending_patch_queue.is_empty();
+    if has_pending_patches {
+        if let Some(pre_patch_command) = &config.patching.pre_patch_command {
+            run_patching_hook("pre-patch", pre_patch_command)
+                .await
+                .context("Patching was aborted")?;
+        }
+    }
+
     // Proceed with actual patching
     log::info!("Applying patches...");
     apply_patches(
         pending_patch_queue,
-        &config,
+        config,
         &cache_file_path,
-        &ui_controller,
-        &mut patcher_thread_rx,
+        ui_controller,
+        patcher_thread_rx,
     )
     .await
     .map_err(|e| match e {
-        InterruptibleFnError::Err(msg) => anyhow!("Failed to apply patches: {}", msg),
-        InterruptibleFnError::Interrupted => anyhow!("Patching was canceled"),
+        InterruptibleFnError::Err(e) => e.context("Failed to apply patches"),
+        InterruptibleFnError::Interrupted => {
+            anyhow::Error::new(CanceledError).context("Patching was canceled")
+        }
     })?;
     log::info!("Done");
+    trim_download_cache(config);
+    if has_pending_patches {
+        if let Some(post_patch_command) = &config.patching.post_patch_command {
+            // Patches have been applied already, only warn in case of failure
+            if let Err(e) = run_patching_hook("post-patch", post_patch_command).await {
+                log::warn!("{:#}", e);
+            }
+        }
+    }
+    if skip_unchanged_plist {
+        record_plist_validator(
+            &plist_validator_file_path,
+            &cache_file_path,
+            remote_patch_list.plist_url,
+            remote_patch_list.etag,
+        )
+        .await;
+    }
     ui_controller
         .dispatch_patching_status(PatchingStatus::Ready)
         .await;
@@ -139,157 +607,1261 @@ async fn interruptible_patcher_routine(
     Ok(())
 }
 
-/// Downloads and parses a 'plist.txt' file located as the URL contained in the
-/// `patch_list_url` argument.
-///
-/// Returns a vector of `ThorPatchInfo` in case of success.
-async fn fetch_patch_list(patch_list_url: Url) -> Result<ThorPatchList> {
-    let resp = reqwest::get(patch_list_url)
+/// Returns the validator of the last applied patch list, if the game client
+/// hasn't been patched (or had its patch index reset) since then.
+async fn current_plist_validator(
+    plist_validator_file_path: &Path,
+    cache_file_path: &Path,
+) -> Option<PatchListValidator> {
+    let validator = read_plist_validator_file(plist_validator_file_path).ok()?;
+    let last_patch_index = read_cache_file(cache_file_path)
         .await
-        .context("Failed to GET URL")?;
-    if !resp.status().is_success() {
-        return Err(anyhow!("Patch list file not found on the remote server"));
+        .ok()
+        .map(|cache| cache.last_patch_index);
+    if validator.last_patch_index == last_patch_index {
+        Some(validator)
+    } else {
+        None
     }
-    let patch_index_content = resp.text().await.context("Invalid responde body")?;
-    log::info!("Parsing patch index...");
-    Ok(thor::patch_list_from_string(patch_index_content.as_str()))
 }
 
-/// Returns the patcher cache file's name as a `PathBuf` on success.
-fn get_cache_file_path() -> Result<PathBuf> {
-    let patcher_name = get_patcher_name()?;
-    Ok(PathBuf::from(patcher_name).with_extension("dat"))
+/// Stores the validator of a patch list that has been fully applied, so that
+/// it isn't processed again until it changes.
+async fn record_plist_validator(
+    plist_validator_file_path: &Path,
+    cache_file_path: &Path,
+    plist_url: Url,
+    etag: Option<String>,
+) {
+    let etag = match etag {
+        Some(v) => v,
+        None => {
+            // Note: Patch lists served without an entity tag are always
+            // processed
+            if plist_validator_file_path.exists() {
+                if let Err(e) = std::fs::remove_file(plist_validator_file_path) {
+                    log::warn!("Failed to remove patch list validator: {}", e);
+                }
+            }
+            return;
+        }
+    };
+    let validator = PatchListValidator {
+        plist_url: plist_url.to_string(),
+        etag,
+        last_patch_index: read_cache_file(cache_file_path)
+            .await
+            .ok()
+            .map(|cache| cache.last_patch_index),
+    };
+    if let Err(e) = write_plist_validator_file(plist_validator_file_path, &validator) {
+        log::warn!("Failed to write patch list validator: {:#}", e);
+    }
 }
 
-/// Downloads a list of patches (described with a `ThorPatchList`).
+/// Fetches the list of patches that haven't been applied yet and sends their
+/// count and total size to the UI, without downloading them.
 ///
-/// Files are downloaded from the remote directory located at the URL
-/// contained in the 'patch_url' argument.
-///
-/// This function is interruptible.
-async fn download_patches_concurrent<P>(
-    patch_url: Url,
-    patch_list: ThorPatchList,
-    download_directory: P,
-    ensure_integrity: bool,
+/// The total size is computed with HEAD requests and is omitted if any of
+/// these requests fails.
+async fn query_pending_patches(
     ui_controller: &UIController,
-    patching_thread_rx: &mut mpsc::Receiver<PatcherCommand>,
-) -> InterruptibleFnResult<Vec<PendingPatch>>
-where
-    P: AsRef<Path>,
-{
-    let patch_count = patch_list.len();
+    config: &PatcherConfiguration,
+) -> Result<()> {
+    let mirrors = mirror_list(config)?;
+    let client = build_http_client(config)?;
+    let mut patch_list =
+        fetch_patch_list_from_mirrors(&client, &mirrors, config, ui_controller).await?;
+    let cache_file_path = get_cache_file_path(config).context("Failed to resolve patcher name")?;
+    filter_applied_patches(
+        &mut patch_list,
+        &cache_file_path,
+        config.patching.strict_plist_sequence.unwrap_or(false),
+    )
+    .await;
+    let patch_batch = PatchBatch::new(&client, &mirrors, patch_list).await;
     ui_controller
-        .dispatch_patching_status(PatchingStatus::DownloadInProgress(0, patch_count, 0))
+        .dispatch_patching_status(PatchingStatus::PendingPatches(
+            patch_batch.patch_list.len(),
+            patch_batch.total_size,
+        ))
         .await;
-    // Download files in a cancelable manner
-    let mut vec = tokio::select! {
-        cancel_res = wait_for_cancellation(patching_thread_rx) => return Err(cancel_res),
-        download_res = download_patches_concurrent_inner(patch_url, patch_list, download_directory, ensure_integrity, ui_controller) => {
-            download_res.map_err(|e| InterruptibleFnError::Err(format!("{:#}", e)))
-        },
-    }?;
-    // Sort patches by index before returning
-    vec.sort_unstable_by(|l, r| l.info.index.cmp(&r.info.index));
-    Ok(vec)
+    Ok(())
 }
 
-/// Actual implementation of the concurrent file download
+/// Removes patches that have already been applied from `patch_list`, according
+/// to the patcher cache.
 ///
-/// Returns an unordered vector of `PendingPatch`.
-async fn download_patches_concurrent_inner<P>(
-    patch_url: Url,
-    patch_list: ThorPatchList,
-    download_directory: P,
-    ensure_integrity: bool,
-    ui_controller: &UIController,
-) -> Result<Vec<PendingPatch>>
-where
-    P: AsRef<Path>,
-{
-    const CONCURRENT_DOWNLOADS: usize = 32;
-    // Shared reqwest client
-    let client = reqwest::Client::new();
-    // Shared value that contains the number of downloaded patches
-    let patch_number = Arc::new(Mutex::new(0 as usize));
-    // Shared tuple that's used to compute the download speed
-    let shared_progress_state = Arc::new(Mutex::new((Instant::now(), 0 as u64)));
-    let one_second = Duration::from_secs(1);
-
-    // Collect stream of "PendingPatch" concurrently with an unordered_buffer
-    let patch_count = patch_list.len();
-    futures::stream::iter(patch_list.into_iter().map(|patch_info| async {
-        let client = &client;
-        let patch_file_url = patch_url
-            .join(patch_info.file_name.as_str())
-            .context("Failed to generate URL for patch file")?;
-        let local_file_path = download_directory
-            .as_ref()
-            .join(patch_info.file_name.as_str());
-        let mut tmp_file = File::create(&local_file_path)
-            .await
-            .context("Failed to create temporary file")?;
-        // Setup a progress callback that'll send the current download speed to the UI
-        let shared_state = shared_progress_state.clone();
-        let patch_number_callback = patch_number.clone();
-        let mut last_downloaded_bytes: u64 = 0;
-        let mut progress_callback = move |dl_now, _| {
-            let dl_delta = dl_now - last_downloaded_bytes;
-            // Return download speed if the required time has elapsed (1s)
-            let downloaded_bytes_per_sec = block_on(async {
-                let mut shared_state = shared_state.lock().await;
-                shared_state.1 += dl_delta;
-                if shared_state.0.elapsed() >= one_second {
-                    let downloaded_bytes_per_sec = (shared_state.1 as f32
-                        / shared_state.0.elapsed().as_secs_f32())
-                    .round() as u64;
-                    shared_state.0 = Instant::now();
-                    shared_state.1 = 0;
-                    Some(downloaded_bytes_per_sec)
-                } else {
-                    None
-                }
-            });
-            // If speed is "available", update UI
-            if let Some(downloaded_bytes_per_sec) = downloaded_bytes_per_sec {
-                block_on(async {
-                    let patch_number = async { *patch_number_callback.lock().await }.await;
-                    ui_controller
-                        .dispatch_patching_status(PatchingStatus::DownloadInProgress(
-                            patch_number,
-                            patch_count,
-                            downloaded_bytes_per_sec,
-                        ))
-                        .await
-                });
+/// Indices of the patch list don't have to be contiguous (e.g., after old
+/// patches have been pruned), patches that follow the cached index are
+/// pending. If `strict_sequence` is `true`, the cached index must be part of
+/// the patch list though, all patches are considered pending otherwise.
+///
+/// Returns the removed patches, in the order of the patch list.
+async fn filter_applied_patches<P: AsRef<Path>>(
+    patch_list: &mut ThorPatchList,
+    cache_file_path: P,
+    strict_sequence: bool,
+) -> ThorPatchList {
+    if let Ok(patcher_cache) = read_cache_file(cache_file_path).await {
+        // Ignore already applied patches if needed
+        // First we verify that our cached index looks relevant (indices
+        // beyond the latest patch mean that the patch list has been reset)
+        let should_filter_patch_list = patch_list.iter().any(|x| {
+            if strict_sequence {
+                x.index == patcher_cache.last_patch_index
+            } else {
+                x.index >= patcher_cache.last_patch_index
             }
-            last_downloaded_bytes = dl_now;
-        };
-
-        download_patch_to_file(
-            client,
-            &patch_file_url,
-            &patch_info,
-            &mut tmp_file,
-            &mut progress_callback,
-        )
-        .await?;
-
-        // Check the archive's integrity if required
-        if ensure_integrity
-            && !is_archive_valid(&local_file_path).context(format!(
-                "Failed to check archive's integrity: '{}'",
-                patch_info.file_name
-            ))?
-        {
-            return Err(anyhow!("Archive '{}' is corrupt", patch_info.file_name));
+        });
+        if should_filter_patch_list {
+            let (pending_patch_list, applied_patch_list) = patch_list
+                .drain(..)
+                .partition(|x| x.index > patcher_cache.last_patch_index);
+            *patch_list = pending_patch_list;
+            return applied_patch_list;
         }
+    };
+    ThorPatchList::new()
+}
 
-        // Update status
-        {
-            // Take the lock and update the current value
-            let mut patch_number = patch_number.lock().await;
-            *patch_number += 1;
+/// Checks that the files of already applied patches are still present in the
+/// game client, and applies the patches whose files are missing or corrupt
+/// again.
+///
+/// This is done like the integrity check, with the given patches only.
+async fn verify_applied_patches(
+    client: &reqwest::Client,
+    mirrors: &[Mirror],
+    applied_patch_list: ThorPatchList,
+    download_directory: &Path,
+    config: &PatcherConfiguration,
+    ui_controller: &UIController,
+    patcher_thread_rx: &mut mpsc::Receiver<PatcherCommand>,
+) -> Result<()> {
+    if applied_patch_list.is_empty() {
+        return Ok(());
+    }
+    let cache_file_path = get_cache_file_path(config).context("Failed to resolve patcher name")?;
+    log::info!(
+        "Verifying {} applied patch(es)...",
+        applied_patch_list.len()
+    );
+    let map_interruptible_err = |e: InterruptibleFnError| match e {
+        InterruptibleFnError::Err(e) => e.context("Failed to verify applied patches"),
+        InterruptibleFnError::Interrupted => {
+            anyhow::Error::new(CanceledError).context("Patching was canceled")
+        }
+    };
+    let applied_patch_queue = download_patches_concurrent(
+        client,
+        mirrors,
+        PatchBatch::new(client, mirrors, applied_patch_list).await,
+        download_directory,
+        config,
+        ui_controller,
+        patcher_thread_rx,
+    )
+    .await
+    .map_err(map_interruptible_err)?;
+    repair_from_patches(
+        applied_patch_queue,
+        config,
+        &cache_file_path,
+        ui_controller,
+        patcher_thread_rx,
+    )
+    .await
+    .map_err(map_interruptible_err)?;
+    log::info!("Done");
+    Ok(())
+}
+
+/// Compares the configured files of the game client with the checksums
+/// recorded when the latest patch was applied, and reports the files that
+/// don't match to the configured URL.
+///
+/// Failures are only logged, as they shouldn't prevent players from patching.
+async fn check_for_tampering(
+    client: &reqwest::Client,
+    latest_patch_index: usize,
+    config: &PatcherConfiguration,
+) {
+    let tamper_check = match &config.patching.tamper_check {
+        Some(v) => v,
+        None => return,
+    };
+    // Note: Checksums are only recorded when patches are applied, so that
+    // the latest patch doesn't have to be downloaded again
+    let patch_checksums = match get_checksums_file_path(config).and_then(read_checksums_file) {
+        Ok(v) if v.patch_index == latest_patch_index => v,
+        _ => {
+            log::info!("No checksums recorded for the latest patch, skipping the tamper check");
+            return;
+        }
+    };
+    log::info!(
+        "Checking the game client against {}...",
+        patch_checksums.patch_name
+    );
+    let (client_name, tampered_files) =
+        match tampered_client_files(&patch_checksums, &tamper_check.files, config) {
+            Ok(v) => v,
+            Err(e) => {
+                log::warn!("Failed to check the game client: {:#}", e);
+                return;
+            }
+        };
+    if tampered_files.is_empty() {
+        log::info!("Done");
+        return;
+    }
+    log::warn!(
+        "{} file(s) don't match {}: {:?}",
+        tampered_files.len(),
+        patch_checksums.patch_name,
+        tampered_files
+    );
+    let report = TamperReport {
+        patch_index: patch_checksums.patch_index,
+        patch_name: &patch_checksums.patch_name,
+        client_name: &client_name,
+        tampered_files: &tampered_files,
+    };
+    if let Err(e) = send_tamper_report(client, &tamper_check.report_url, &report).await {
+        log::warn!("Failed to report tampered files: {:#}", e);
+    }
+}
+
+/// Returns the files among `relative_paths` whose content doesn't match the
+/// given checksums, along with the name of the GRF (or directory) they were
+/// read from.
+fn tampered_client_files(
+    patch_checksums: &PatchChecksums,
+    relative_paths: &[String],
+    config: &PatcherConfiguration,
+) -> Result<(String, Vec<TamperedFile>)> {
+    let checksums = &patch_checksums.checksums;
+    let target_directory = get_target_directory(config)?;
+    match &patch_checksums.target_grf_name {
+        Some(grf_name) => {
+            let grf_path = target_directory.join(grf_name);
+            let mut grf_archive = if grf_path.exists() {
+                Some(GrfArchive::open(grf_path)?)
+            } else {
+                None
+            };
+            let tampered_files = find_tampered_files(checksums, relative_paths, |relative_path| {
+                match &mut grf_archive {
+                    Some(grf_archive) if grf_archive.contains_file(relative_path) => {
+                        Ok(Some(grf_archive.read_file_content(relative_path)?))
+                    }
+                    _ => Ok(None),
+                }
+            })?;
+            Ok((grf_name.clone(), tampered_files))
+        }
+        None => {
+            let tampered_files = find_tampered_files(checksums, relative_paths, |relative_path| {
+                let file_path = join_windows_relative_path(&target_directory, relative_path)?;
+                if file_path.is_file() {
+                    Ok(Some(std::fs::read(file_path)?))
+                } else {
+                    Ok(None)
+                }
+            })?;
+            // Note: The absolute path of the game client isn't sent, as it
+            // can contain the name of the player's user account
+            let client_name = config
+                .patching
+                .staging_data_dir
+                .clone()
+                .unwrap_or_else(|| ".".to_string());
+            Ok((client_name, tampered_files))
+        }
+    }
+}
+
+/// Checks that there's enough free space on the target volume to download and
+/// apply patches whose total size is `total_patch_size`.
+fn check_disk_space(total_patch_size: u64, config: &PatcherConfiguration) -> Result<()> {
+    let target_directory = get_target_directory(config)?;
+    let required_bytes = required_disk_space(total_patch_size, config, &target_directory);
+    let available_bytes = fs2::available_space(&target_directory)
+        .context("Failed to retrieve the available disk space")?;
+    log::info!(
+        "Disk space: {} bytes required, {} bytes available",
+        required_bytes,
+        available_bytes
+    );
+    if available_bytes < required_bytes {
+        return Err(InsufficientDiskSpaceError {
+            required_bytes,
+            available_bytes,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Returns a conservative estimate of the disk space needed to download and
+/// apply patches whose total size is `total_patch_size`.
+fn required_disk_space(
+    total_patch_size: u64,
+    config: &PatcherConfiguration,
+    target_directory: &Path,
+) -> u64 {
+    // Note: Patches are downloaded first and their content is then copied to
+    // GRFs (or the game client's directory) as is
+    let mut required_bytes = total_patch_size.saturating_mul(2);
+    if config.patching.grf_merge_strategy() == GrfMergeStrategy::TempSwap {
+        // GRFs patched out-of-place are rewritten next to the original
+        let grf_path = target_directory.join(&config.client.default_grf_name);
+        if let Ok(metadata) = std::fs::metadata(grf_path) {
+            required_bytes = required_bytes.saturating_add(metadata.len());
+        }
+    }
+    required_bytes
+}
+
+/// Computes the total size of the patches of `patch_list` with HEAD requests.
+async fn fetch_patches_total_size(
+    client: &reqwest::Client,
+    mirrors: &[Mirror],
+    patch_list: &[ThorPatchInfo],
+) -> Result<u64> {
+    const CONCURRENT_REQUESTS: usize = 8;
+    // Note: Futures are collected beforehand to keep the resulting future `Send`
+    let size_requests: Vec<_> = patch_list
+        .iter()
+        .map(|patch_info| fetch_patch_size_from_mirrors(client, mirrors, patch_info))
+        .collect();
+    let patch_sizes: Vec<u64> = futures::stream::iter(size_requests)
+        .buffer_unordered(CONCURRENT_REQUESTS)
+        .try_collect()
+        .await?;
+    Ok(patch_sizes.iter().sum())
+}
+
+/// Retrieves the size of a single patch from the first mirror that responds,
+/// by order of priority.
+async fn fetch_patch_size_from_mirrors(
+    client: &reqwest::Client,
+    mirrors: &[Mirror],
+    patch: &ThorPatchInfo,
+) -> Result<u64> {
+    let mut last_error = anyhow!("No mirror available");
+    for mirror in mirrors {
+        match fetch_patch_size(client, mirror, patch).await {
+            Ok(patch_size) => return Ok(patch_size),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+/// Retrieves the size of a single patch with a HEAD request.
+///
+/// Like downloads, requests refused with templated URLs are retried once
+/// with a new token.
+async fn fetch_patch_size(
+    client: &reqwest::Client,
+    mirror: &Mirror,
+    patch: &ThorPatchInfo,
+) -> Result<u64> {
+    let (patch_file_url, token) = mirror.patch_file_url(client, patch).await?;
+    let mut resp = client
+        .head(patch_file_url)
+        .send()
+        .await
+        .context(format!("Failed to request size of '{}'", patch.file_name))?;
+    if let (Some(signed_urls), Some(token)) = (&mirror.signed_urls, token) {
+        if resp.status() == StatusCode::FORBIDDEN {
+            // The token might have expired, retry once with a new one
+            signed_urls.refresh_token(client, &token).await?;
+            let (patch_file_url, _) = mirror.patch_file_url(client, patch).await?;
+            resp = client
+                .head(patch_file_url)
+                .send()
+                .await
+                .context(format!("Failed to request size of '{}'", patch.file_name))?;
+        }
+    }
+    if !resp.status().is_success() {
+        return Err(
+            anyhow::Error::new(HttpStatusError(resp.status())).context(format!(
+                "Patch file '{}' not found on the remote server",
+                patch.file_name
+            )),
+        );
+    }
+    // Note: `Response::content_length` can't be used here since the body of
+    // HEAD responses is always empty
+    resp.headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok())
+        .ok_or_else(|| anyhow!("Size of '{}' is unknown", patch.file_name))
+}
+
+/// Main routine of the integrity check task.
+///
+/// All the patches of the patch list are downloaded and used as a reference:
+/// the latest version of each file is compared with the content of the game
+/// client's files, and the patches containing files that don't match are
+/// applied again.
+///
+/// This routine is interruptible, like the patching routine.
+async fn interruptible_repair_routine(
+    ui_controller: &UIController,
+    config: &PatcherConfiguration,
+    patcher_thread_rx: &mut mpsc::Receiver<PatcherCommand>,
+) -> Result<()> {
+    log::info!("Integrity check started");
+    let mirrors = mirror_list(config)?;
+    let client = build_http_client(config)?;
+    let patch_list =
+        fetch_patch_list_from_mirrors(&client, &mirrors, config, ui_controller).await?;
+    log::info!("Successfully fetched patch list: {:?}", patch_list);
+    let cache_file_path = get_cache_file_path(config).context("Failed to resolve patcher name")?;
+
+    // Fetch all patch files, regardless of the cache
+    log::info!("Downloading patches... ");
+    let download_directory = DownloadDirectory::new(config)?;
+    let pending_patch_queue = download_patches_concurrent(
+        &client,
+        &mirrors,
+        PatchBatch::new(&client, &mirrors, patch_list).await,
+        download_directory.path(),
+        config,
+        ui_controller,
+        patcher_thread_rx,
+    )
+    .await
+    .map_err(|e| match e {
+        InterruptibleFnError::Err(e) => e.context("Failed to download patches"),
+        InterruptibleFnError::Interrupted => {
+            anyhow::Error::new(CanceledError).context("Integrity check was canceled")
+        }
+    })?;
+    log::info!("Done");
+
+    log::info!("Verifying files...");
+    repair_from_patches(
+        pending_patch_queue,
+        config,
+        &cache_file_path,
+        ui_controller,
+        patcher_thread_rx,
+    )
+    .await
+    .map_err(|e| match e {
+        InterruptibleFnError::Err(e) => e.context("Failed to repair files"),
+        InterruptibleFnError::Interrupted => {
+            anyhow::Error::new(CanceledError).context("Integrity check was canceled")
+        }
+    })?;
+    log::info!("Done");
+    trim_download_cache(config);
+    ui_controller
+        .dispatch_patching_status(PatchingStatus::Ready)
+        .await;
+    log::info!("Integrity check finished!");
+    Ok(())
+}
+
+/// Returns the list of mirrors to download files from, by order of priority.
+///
+/// The main URLs of the configuration come first, followed by the configured
+/// fallback mirrors.
+fn mirror_list(config: &PatcherConfiguration) -> Result<Vec<Mirror>> {
+    let mut mirrors = vec![Mirror {
+        plist_url: Url::parse(config.web.plist_url.as_str())
+            .context("Failed to parse 'plist_url'")?,
+        patch_url: Url::parse(config.web.patch_url.as_str())
+            .context("Failed to parse 'patch_url'")?,
+        // Note: Templated URLs only apply to the primary server
+        signed_urls: match &config.web.patch_url_template {
+            Some(template) => Some(SignedUrlBuilder::new(
+                template,
+                config.web.token_url.as_deref(),
+            )?),
+            None => None,
+        },
+    }];
+    if let Some(fallback_mirrors) = &config.web.mirrors {
+        for mirror in fallback_mirrors {
+            mirrors.push(Mirror {
+                plist_url: Url::parse(mirror.plist_url.as_str()).context(format!(
+                    "Failed to parse mirror's 'plist_url': '{}'",
+                    mirror.plist_url
+                ))?,
+                patch_url: Url::parse(mirror.patch_url.as_str()).context(format!(
+                    "Failed to parse mirror's 'patch_url': '{}'",
+                    mirror.patch_url
+                ))?,
+                signed_urls: None,
+            });
+        }
+    }
+    Ok(mirrors)
+}
+
+/// Fetches the patch list from the first mirror that responds, by order of
+/// priority.
+///
+/// Failed requests are retried with an exponential backoff before moving on
+/// to the next mirror.
+async fn fetch_patch_list_from_mirrors(
+    client: &reqwest::Client,
+    mirrors: &[Mirror],
+    config: &PatcherConfiguration,
+    ui_controller: &UIController,
+) -> Result<ThorPatchList> {
+    fetch_patch_list_from_mirrors_if_modified(client, mirrors, config, ui_controller, None)
+        .await?
+        .map(|remote_patch_list| remote_patch_list.patch_list)
+        .ok_or_else(|| anyhow!("Patch list unexpectedly reported as not modified"))
+}
+
+/// Fetches the patch list like `fetch_patch_list_from_mirrors`, unless it
+/// matches `validator`.
+///
+/// Returns `None` if the mirror `validator` comes from reports that the patch
+/// list hasn't been modified.
+async fn fetch_patch_list_from_mirrors_if_modified(
+    client: &reqwest::Client,
+    mirrors: &[Mirror],
+    config: &PatcherConfiguration,
+    ui_controller: &UIController,
+    validator: Option<&PatchListValidator>,
+) -> Result<Option<RemotePatchList>> {
+    let (max_retries, retry_base_delay) = retry_policy(config);
+    let verifier = signature_verifier(config)?;
+    let plist_format = config.web.plist_format.unwrap_or(PatchListFormat::Text);
+    let read_timeout = read_timeout(config);
+    let mut last_error = anyhow!("No mirror available");
+    for (mirror_index, mirror) in mirrors.iter().enumerate() {
+        if mirror_index > 0 {
+            log::warn!("Switching to mirror '{}'", mirror.plist_url);
+            ui_controller
+                .dispatch_patching_status(PatchingStatus::UsingMirror(mirror.plist_url.to_string()))
+                .await;
+        }
+        // Note: Entity tags are specific to the mirror that returned them
+        let etag = validator
+            .filter(|validator| validator.plist_url == mirror.plist_url.as_str())
+            .map(|validator| validator.etag.as_str());
+        let mut retry = 0;
+        loop {
+            match fetch_patch_list(
+                client,
+                mirror.plist_url.clone(),
+                plist_format,
+                read_timeout,
+                verifier.as_ref(),
+                etag,
+            )
+            .await
+            {
+                Ok(remote_patch_list) => return Ok(remote_patch_list),
+                Err(e) => {
+                    if retry >= max_retries || is_permanent_failure(&e) {
+                        last_error = with_connection_hint(e, config);
+                        break;
+                    }
+                    retry += 1;
+                    log::warn!("{:#}, retrying ({}/{})", e, retry, max_retries);
+                    ui_controller
+                        .dispatch_patching_status(PatchingStatus::DownloadRetry(
+                            mirror.plist_url.to_string(),
+                            retry,
+                            max_retries,
+                        ))
+                        .await;
+                    time::delay_for(retry_delay(retry_base_delay, retry)).await;
+                }
+            }
+        }
+    }
+    Err(last_error.context("Failed to retrieve the patch list"))
+}
+
+/// Downloads and parses a 'plist.txt' file located as the URL contained in the
+/// `patch_list_url` argument.
+///
+/// The file is parsed according to `plist_format`. If a `verifier` is given,
+/// the patch list's signature is checked before parsing it.
+///
+/// If an `etag` is given, the request is conditional and `None` is returned
+/// when the server reports that the patch list hasn't been modified.
+async fn fetch_patch_list(
+    client: &reqwest::Client,
+    patch_list_url: Url,
+    plist_format: PatchListFormat,
+    read_timeout: Option<Duration>,
+    verifier: Option<&SignatureVerifier>,
+    etag: Option<&str>,
+) -> Result<Option<RemotePatchList>> {
+    let mut request = client.get(patch_list_url.clone());
+    if let Some(etag) = etag {
+        request = request.header(header::IF_NONE_MATCH, etag);
+    }
+    let resp = with_read_timeout(read_timeout, request.send())
+        .await
+        .context("Failed to GET URL")?;
+    if etag.is_some() && resp.status() == StatusCode::NOT_MODIFIED {
+        return Ok(None);
+    }
+    if !resp.status().is_success() {
+        return Err(anyhow::Error::new(HttpStatusError(resp.status()))
+            .context("Patch list file not found on the remote server"));
+    }
+    let response_etag = resp
+        .headers()
+        .get(header::ETAG)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string());
+    let patch_index_content = with_read_timeout(read_timeout, resp.text())
+        .await
+        .context("Invalid responde body")?;
+    if let Some(verifier) = verifier {
+        let signature = fetch_signature(client, &patch_list_url)
+            .await
+            .context("Failed to retrieve the patch list's signature")?;
+        verifier
+            .verify(patch_index_content.as_bytes(), &signature)
+            .context("Patch list's signature is invalid")?;
+    }
+    log::info!("Parsing patch index...");
+    let patch_list = parse_patch_list(patch_index_content.as_str(), plist_format)
+        .context("Failed to parse the patch list")?;
+    Ok(Some(RemotePatchList {
+        patch_list,
+        plist_url: patch_list_url,
+        etag: response_etag,
+    }))
+}
+
+/// Returns the verifier used to check the signatures of the patch list and
+/// patches, or `None` if no public key is configured.
+fn signature_verifier(config: &PatcherConfiguration) -> Result<Option<SignatureVerifier>> {
+    config
+        .web
+        .public_key
+        .as_ref()
+        .map(|public_key| {
+            SignatureVerifier::from_hex(public_key).context("Invalid public key in configuration")
+        })
+        .transpose()
+}
+
+/// Builds the HTTP client used for all requests of a task.
+///
+/// A single client is used per task, so that connections are reused between
+/// the patch list and the patches.
+///
+/// The configured proxy is used if any, otherwise the proxies set with the
+/// `HTTP_PROXY` and `HTTPS_PROXY` environment variables are used.
+fn build_http_client(config: &PatcherConfiguration) -> Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder().default_headers(default_headers(&config.web)?);
+    if let Some(proxy_url) = &config.web.proxy {
+        let proxy = reqwest::Proxy::all(proxy_url.as_str())
+            .context(format!("Invalid proxy URL: '{}'", proxy_url))?;
+        builder = builder.proxy(proxy);
+    }
+    if let Some(connect_timeout_ms) = config.web.connect_timeout_ms {
+        builder = builder.connect_timeout(Duration::from_millis(connect_timeout_ms));
+    }
+    let certificate_pins = certificate_pins(config)?;
+    if !certificate_pins.is_empty() {
+        // Note: Pinning is implemented on top of rustls, the default TLS
+        // backend is used otherwise
+        builder = builder.use_preconfigured_tls(pinned_tls_config(certificate_pins));
+    }
+    builder.build().context("Failed to build HTTP client")
+}
+
+/// Returns `true` if `err` was caused by the remote server refusing access to
+/// a file.
+fn is_access_denied(err: &anyhow::Error) -> bool {
+    err.chain()
+        .any(|cause| cause.downcast_ref::<AccessDeniedError>().is_some())
+}
+
+/// Returns `true` if `err` was caused by a connection or read timeout.
+pub(super) fn is_timeout(err: &anyhow::Error) -> bool {
+    err.chain()
+        .any(|e| match e.downcast_ref::<reqwest::Error>() {
+            Some(e) => e.is_timeout(),
+            None => e.is::<ReadTimeoutError>(),
+        })
+}
+
+/// Returns the maximum duration to wait for data from the server, if any.
+fn read_timeout(config: &PatcherConfiguration) -> Option<Duration> {
+    config.web.read_timeout_ms.map(Duration::from_millis)
+}
+
+/// Awaits `future` and fails with a `ReadTimeoutError` if it doesn't complete
+/// within `read_timeout`.
+async fn with_read_timeout<T, E, F>(read_timeout: Option<Duration>, future: F) -> Result<T>
+where
+    E: std::error::Error + Send + Sync + 'static,
+    F: Future<Output = std::result::Result<T, E>>,
+{
+    let result = match read_timeout {
+        Some(read_timeout) => time::timeout(read_timeout, future)
+            .await
+            .map_err(|_| anyhow::Error::new(ReadTimeoutError))?,
+        None => future.await,
+    };
+    Ok(result?)
+}
+
+/// Returns the certificate pins of the hosts of the configured URLs and
+/// mirrors.
+///
+/// The pins of the main server also apply to the hosts of
+/// `patch_url_template` and `token_url`, since they serve patches and tokens
+/// in its place.
+fn certificate_pins(config: &PatcherConfiguration) -> Result<CertificatePins> {
+    let mut pins = CertificatePins::default();
+    let mut add_pins = |urls: &[&String], fingerprints: &Option<Vec<String>>| -> Result<()> {
+        let fingerprints = match fingerprints {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+        for url in urls {
+            let url = Url::parse(url).context(format!("Failed to parse URL: '{}'", url))?;
+            if let Some(host) = url.host_str() {
+                pins.add(host, fingerprints)?;
+            }
+        }
+        Ok(())
+    };
+    let mut urls = vec![&config.web.plist_url, &config.web.patch_url];
+    urls.extend(config.web.patch_url_template.iter());
+    urls.extend(config.web.token_url.iter());
+    add_pins(&urls, &config.web.certificate_pins)?;
+    if let Some(mirrors) = &config.web.mirrors {
+        for mirror in mirrors {
+            add_pins(
+                &[&mirror.plist_url, &mirror.patch_url],
+                &mirror.certificate_pins,
+            )?;
+        }
+    }
+    Ok(pins)
+}
+
+/// Adds a hint to errors caused by connection failures: a security warning
+/// for certificate pin mismatches, a hint about the network for timeouts, or
+/// a hint about the proxy settings when a proxy is in use.
+fn with_connection_hint(err: anyhow::Error, config: &PatcherConfiguration) -> anyhow::Error {
+    if is_pin_mismatch(&err) {
+        return err.context(
+            "Security error: the server's certificate doesn't match the pinned one, \
+             the connection might have been intercepted",
+        );
+    }
+    if is_timeout(&err) {
+        return err
+            .context("The server took too long to respond, please check your network connection");
+    }
+    let uses_proxy = config.web.proxy.is_some()
+        || ["HTTP_PROXY", "http_proxy", "HTTPS_PROXY", "https_proxy"]
+            .iter()
+            .any(|var| env::var_os(var).is_some());
+    let is_connection_error = err
+        .chain()
+        .filter_map(|e| e.downcast_ref::<reqwest::Error>())
+        .any(|e| e.is_connect());
+    if uses_proxy && is_connection_error {
+        err.context("Failed to connect through the proxy, please check your proxy settings")
+    } else {
+        err
+    }
+}
+
+/// Returns the maximum number of retries and the base retry delay to use for
+/// failed requests.
+fn retry_policy(config: &PatcherConfiguration) -> (usize, Duration) {
+    let max_retries = config.web.max_retries.unwrap_or(DEFAULT_MAX_RETRIES);
+    let retry_base_delay = Duration::from_millis(
+        config
+            .web
+            .retry_base_delay_ms
+            .unwrap_or(DEFAULT_RETRY_BASE_DELAY_MS),
+    );
+    (max_retries, retry_base_delay)
+}
+
+/// Computes the delay to wait before a retry, with an exponential backoff.
+///
+/// `retry` starts at 1 for the first retry. Delays are capped at
+/// `MAX_RETRY_DELAY`.
+fn retry_delay(base_delay: Duration, retry: usize) -> Duration {
+    const MAX_EXPONENT: usize = 16;
+    let exponent = retry.saturating_sub(1).min(MAX_EXPONENT) as u32;
+    base_delay
+        .checked_mul(2u32.pow(exponent))
+        .unwrap_or(MAX_RETRY_DELAY)
+        .min(MAX_RETRY_DELAY)
+}
+
+/// Returns `true` if `err` won't go away by retrying the same request (e.g.,
+/// a file that doesn't exist on the remote server).
+fn is_permanent_failure(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        if cause.downcast_ref::<AccessDeniedError>().is_some() {
+            return true;
+        }
+        match cause.downcast_ref::<HttpStatusError>() {
+            Some(HttpStatusError(status)) => {
+                status.is_client_error()
+                    && *status != StatusCode::REQUEST_TIMEOUT
+                    && *status != StatusCode::TOO_MANY_REQUESTS
+            }
+            None => false,
+        }
+    })
+}
+
+/// Runs a hook command and waits for it to exit.
+///
+/// Returns an error if the command couldn't be started or exited with a
+/// non-zero code.
+async fn run_patching_hook(hook_name: &str, command: &CommandConfiguration) -> Result<()> {
+    log::info!("Running {} command: {}", hook_name, command.path);
+    let status = tokio::process::Command::new(&command.path)
+        .args(&command.arguments)
+        .status()
+        .await
+        .context(format!("Failed to run {} command", hook_name))?;
+    if !status.success() {
+        return Err(anyhow!("The {} command failed ({})", hook_name, status));
+    }
+    Ok(())
+}
+
+/// Removes the oldest patches from the download cache (if any) until its size
+/// goes below the configured limit.
+///
+/// This must only be called once all the downloaded patches have been applied.
+fn trim_download_cache(config: &PatcherConfiguration) {
+    if let (Some(cache_directory), Some(max_cache_size)) = (
+        download_cache_directory(config),
+        config.patching.max_cache_size,
+    ) {
+        if let Err(e) = trim_cache_directory(cache_directory, max_cache_size) {
+            log::warn!("Failed to trim the download cache: {:#}", e);
+        }
+    }
+}
+
+/// Adds an applied patch to the history file.
+///
+/// Failures are only logged since the history is informative.
+fn record_applied_patch(config: &PatcherConfiguration, patch_index: usize, patch_name: String) {
+    let applied_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let result = get_history_file_path(config).and_then(|history_file_path| {
+        append_history_entry(
+            history_file_path,
+            PatchHistoryEntry {
+                index: patch_index,
+                file_name: patch_name,
+                applied_at,
+            },
+            MAX_HISTORY_ENTRIES,
+        )
+    });
+    if let Err(e) = result {
+        log::warn!("Failed to write history file: {:#}.", e);
+    }
+}
+
+/// Keeps the checksums of the files checked by the tamper check (if enabled)
+/// from an applied patch.
+///
+/// Failures are only logged since the tamper check is informative.
+fn record_patch_checksums(
+    config: &PatcherConfiguration,
+    patch_index: usize,
+    patch_name: &str,
+    patch_file_path: &Path,
+) {
+    let tamper_check = match &config.patching.tamper_check {
+        Some(v) => v,
+        None => return,
+    };
+    let checksums_file_path = match get_checksums_file_path(config) {
+        Ok(v) => v,
+        Err(e) => {
+            log::warn!("Failed to resolve patcher name: {:#}.", e);
+            return;
+        }
+    };
+    let result = patch_checksums(
+        patch_index,
+        patch_name,
+        patch_file_path,
+        &tamper_check.files,
+        config,
+    )
+    .and_then(|patch_checksums| write_checksums_file(&checksums_file_path, &patch_checksums));
+    if let Err(e) = result {
+        log::warn!("Failed to record the checksums of {}: {:#}.", patch_name, e);
+        // Note: The checksums of previous patches might not apply anymore
+        let _ = std::fs::remove_file(&checksums_file_path);
+    }
+}
+
+/// Returns the checksums of the given files contained in a patch.
+fn patch_checksums(
+    patch_index: usize,
+    patch_name: &str,
+    patch_file_path: &Path,
+    relative_paths: &[String],
+    config: &PatcherConfiguration,
+) -> Result<PatchChecksums> {
+    if is_rgz_patch(patch_file_path) {
+        return Err(anyhow!("RGZ patches don't contain checksums"));
+    }
+    let mut thor_archive = open_thor_archive(patch_file_path)?;
+    let checksums = thor_archive
+        .checksums()
+        .context("Failed to read the patch's checksums")?
+        .into_iter()
+        .filter(|(relative_path, _)| relative_paths.contains(relative_path))
+        .collect();
+    Ok(PatchChecksums {
+        patch_index,
+        patch_name: patch_name.to_string(),
+        target_grf_name: patch_target_grf_name(&thor_archive, config),
+        checksums,
+    })
+}
+
+/// Returns the directory patches are applied to: the staging directory if one
+/// is configured, the game client's directory otherwise.
+fn get_target_directory(config: &PatcherConfiguration) -> Result<PathBuf> {
+    let current_working_dir = env::current_dir()?;
+    match &config.patching.staging_data_dir {
+        Some(staging_data_dir) => {
+            let staging_data_dir = current_working_dir.join(staging_data_dir);
+            std::fs::create_dir_all(&staging_data_dir).context(format!(
+                "Failed to create staging directory '{}'",
+                staging_data_dir.display()
+            ))?;
+            Ok(staging_data_dir)
+        }
+        None => Ok(current_working_dir),
+    }
+}
+
+/// Returns the path of a GRF patched in `target_directory`.
+///
+/// When patching a staging install, the game client's GRF is copied to the
+/// staging directory first (if not done already), so that the live GRF is
+/// never modified.
+fn get_target_grf_path(
+    config: &PatcherConfiguration,
+    target_directory: &Path,
+    grf_name: &str,
+) -> Result<PathBuf> {
+    let target_grf_path = target_directory.join(grf_name);
+    if config.patching.staging_data_dir.is_some() {
+        let live_grf_path = env::current_dir()?.join(grf_name);
+        stage_file(&live_grf_path, &target_grf_path)?;
+    }
+    Ok(target_grf_path)
+}
+
+/// Copies `live_file_path` to `staged_file_path`, unless the staged file
+/// already exists or there's nothing to copy.
+fn stage_file(live_file_path: &Path, staged_file_path: &Path) -> Result<()> {
+    if staged_file_path.exists() || !live_file_path.exists() {
+        return Ok(());
+    }
+    log::info!(
+        "Copying {} to {}",
+        live_file_path.display(),
+        staged_file_path.display()
+    );
+    if let Some(parent) = staged_file_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::copy(live_file_path, staged_file_path)?;
+    Ok(())
+}
+
+/// List of patches to download, along with their total size (if known).
+struct PatchBatch {
+    patch_list: ThorPatchList,
+    total_size: Option<u64>,
+}
+
+impl PatchBatch {
+    /// Computes the total size of the patches of `patch_list`, which is
+    /// omitted if it cannot be retrieved.
+    async fn new(client: &reqwest::Client, mirrors: &[Mirror], patch_list: ThorPatchList) -> Self {
+        let total_size = match fetch_patches_total_size(client, mirrors, &patch_list).await {
+            Ok(v) => Some(v),
+            Err(e) => {
+                log::warn!("Failed to compute the size of pending patches: {:#}", e);
+                None
+            }
+        };
+        Self {
+            patch_list,
+            total_size,
+        }
+    }
+}
+
+/// Downloads a batch of patches.
+///
+/// Each file is downloaded from the first mirror that responds, by order of
+/// priority.
+///
+/// This function is interruptible.
+async fn download_patches_concurrent<P>(
+    client: &reqwest::Client,
+    mirrors: &[Mirror],
+    patch_batch: PatchBatch,
+    download_directory: P,
+    config: &PatcherConfiguration,
+    ui_controller: &UIController,
+    patching_thread_rx: &mut mpsc::Receiver<PatcherCommand>,
+) -> InterruptibleFnResult<Vec<PendingPatch>>
+where
+    P: AsRef<Path>,
+{
+    let patch_count = patch_batch.patch_list.len();
+    ui_controller
+        .dispatch_patching_status(PatchingStatus::DownloadInProgress(0, patch_count, 0))
+        .await;
+    // Download files in a cancelable manner
+    let mut vec = tokio::select! {
+        cancel_res = wait_for_cancellation(patching_thread_rx) => return Err(cancel_res),
+        download_res = download_patches_concurrent_inner(client, mirrors, patch_batch, download_directory, config, ui_controller) => {
+            download_res.map_err(InterruptibleFnError::Err)
+        },
+    }?;
+    // Sort patches by index before returning
+    vec.sort_unstable_by(|l, r| l.info.index.cmp(&r.info.index));
+    Ok(vec)
+}
+
+/// Actual implementation of the concurrent file download
+///
+/// Returns an unordered vector of `PendingPatch`.
+async fn download_patches_concurrent_inner<P>(
+    client: &reqwest::Client,
+    mirrors: &[Mirror],
+    patch_batch: PatchBatch,
+    download_directory: P,
+    config: &PatcherConfiguration,
+    ui_controller: &UIController,
+) -> Result<Vec<PendingPatch>>
+where
+    P: AsRef<Path>,
+{
+    // Note: At least one download must be running at a time
+    let concurrent_downloads = config
+        .web
+        .concurrent_downloads
+        .unwrap_or(DEFAULT_CONCURRENT_DOWNLOADS)
+        .max(1);
+    let ensure_integrity = config.patching.verify_checksums();
+    let (max_retries, retry_base_delay) = retry_policy(config);
+    // Shared signature verifier (if any)
+    let verifier = signature_verifier(config)?;
+    // Shared bandwidth limiter (if any)
+    let throttle = BandwidthThrottle::new(config.web.max_download_speed.unwrap_or(0));
+    let read_timeout = read_timeout(config);
+    // Shared value that contains the number of downloaded patches
+    let patch_number = Arc::new(Mutex::new(0 as usize));
+    // Downloaded patches are registered in the download cache (if any)
+    let is_cache_directory = download_cache_directory(config).is_some();
+    let cache_manifest_lock = Mutex::new(());
+    // Shared state that's used to compute download metrics
+    let shared_metrics = Arc::new(Mutex::new(DownloadMetrics::new(patch_batch.total_size)));
+    let patch_list = patch_batch.patch_list;
+
+    // Collect stream of "PendingPatch" concurrently with an unordered_buffer
+    let patch_count = patch_list.len();
+    futures::stream::iter(patch_list.into_iter().map(|patch_info| async {
+        let verifier = verifier.as_ref();
+        let throttle = throttle.as_ref();
+        let local_file_path = download_directory
+            .as_ref()
+            .join(patch_info.file_name.as_str());
+        // Note: Patches are downloaded under a partial name and only renamed
+        // once complete. Existing files are kept so that their download can
+        // be resumed (which also checks previously downloaded patches).
+        let partial_file_path = partial_file_path(&local_file_path);
+        if local_file_path.exists() && !partial_file_path.exists() {
+            fs::rename(&local_file_path, &partial_file_path)
+                .await
+                .context("Failed to rename temporary file")?;
+        }
+        let mut tmp_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&partial_file_path)
+            .await
+            .context("Failed to create temporary file")?;
+        // Setup a progress callback that'll send download metrics to the UI
+        let shared_metrics = shared_metrics.clone();
+        let patch_number_callback = patch_number.clone();
+        let mut last_downloaded_bytes: u64 = 0;
+        let mut progress_callback = move |dl_now: u64, _| {
+            // Note: `dl_now` goes backward when a download is restarted from scratch
+            let dl_delta = dl_now.saturating_sub(last_downloaded_bytes);
+            // Return a metrics snapshot if the required time has elapsed
+            let metrics = block_on(async { shared_metrics.lock().await.record(dl_delta) });
+            // If metrics are "available", update UI
+            if let Some(metrics) = metrics {
+                block_on(async {
+                    let patch_number = async { *patch_number_callback.lock().await }.await;
+                    ui_controller
+                        .dispatch_patching_status(PatchingStatus::DownloadInProgress(
+                            patch_number,
+                            patch_count,
+                            metrics.bytes_per_sec,
+                        ))
+                        .await;
+                    ui_controller
+                        .dispatch_patching_status(PatchingStatus::DownloadMetrics(
+                            metrics.downloaded_bytes,
+                            metrics.total_bytes,
+                            metrics.bytes_per_sec,
+                            metrics.remaining_secs,
+                        ))
+                        .await
+                });
+            }
+            last_downloaded_bytes = dl_now;
+        };
+
+        let mut download_res: Result<()> = Err(anyhow!("No mirror available"));
+        for (mirror_index, mirror) in mirrors.iter().enumerate() {
+            if mirror_index > 0 {
+                log::warn!("Switching to mirror '{}'", mirror.patch_url);
+                ui_controller
+                    .dispatch_patching_status(PatchingStatus::UsingMirror(
+                        mirror.patch_url.to_string(),
+                    ))
+                    .await;
+            }
+            let mut retry = 0;
+            let mut token_refreshed = false;
+            download_res = loop {
+                let (patch_file_url, token) = match mirror.patch_file_url(client, &patch_info).await
+                {
+                    Ok(v) => v,
+                    Err(e) => break Err(e),
+                };
+                let res = match download_patch_to_file(
+                    client,
+                    &patch_file_url,
+                    &patch_info,
+                    &mut tmp_file,
+                    throttle,
+                    read_timeout,
+                    &mut progress_callback,
+                )
+                .await
+                {
+                    Ok(()) => match verifier {
+                        Some(verifier) => {
+                            verify_patch_signature(
+                                client,
+                                verifier,
+                                &patch_file_url,
+                                &patch_info,
+                                &partial_file_path,
+                                &mut tmp_file,
+                            )
+                            .await
+                        }
+                        None => Ok(()),
+                    },
+                    Err(e) => Err(e),
+                };
+                // Check the archive's integrity if required
+                let res = match res {
+                    Ok(()) if ensure_integrity => {
+                        verify_patch_integrity(&patch_info, &partial_file_path, &mut tmp_file).await
+                    }
+                    res => res,
+                };
+                match res {
+                    Ok(()) => break Ok(()),
+                    Err(e) => {
+                        if let (Some(signed_urls), Some(token)) = (&mirror.signed_urls, token) {
+                            if !token_refreshed && is_access_denied(&e) {
+                                // The token might have expired, retry once with a new one
+                                log::warn!("{:#}, refreshing token", e);
+                                token_refreshed = true;
+                                match signed_urls.refresh_token(client, &token).await {
+                                    Ok(()) => continue,
+                                    Err(e) => break Err(e),
+                                }
+                            }
+                        }
+                        if retry >= max_retries || is_permanent_failure(&e) {
+                            break Err(with_connection_hint(e, config));
+                        }
+                        retry += 1;
+                        log::warn!("{:#}, retrying ({}/{})", e, retry, max_retries);
+                        ui_controller
+                            .dispatch_patching_status(PatchingStatus::DownloadRetry(
+                                patch_info.file_name.clone(),
+                                retry,
+                                max_retries,
+                            ))
+                            .await;
+                        time::delay_for(retry_delay(retry_base_delay, retry)).await;
+                    }
+                }
+            };
+            if download_res.is_ok() {
+                break;
+            }
+        }
+        download_res?;
+
+        // Note: Open files cannot be renamed on Windows
+        drop(tmp_file);
+        fs::rename(&partial_file_path, &local_file_path)
+            .await
+            .context("Failed to rename temporary file")?;
+        if is_cache_directory {
+            let _lock = cache_manifest_lock.lock().await;
+            // Note: Unregistered patches are only left out of cache trimming
+            if let Err(e) = register_cached_file(download_directory.as_ref(), &patch_info.file_name)
+            {
+                log::warn!(
+                    "Failed to register '{}' in the download cache: {:#}",
+                    patch_info.file_name,
+                    e
+                );
+            }
+        }
+
+        // Update status
+        {
+            // Take the lock and update the current value
+            let mut patch_number = patch_number.lock().await;
+            *patch_number += 1;
         }
         // File's been downloaded, add it to the queue
         Ok(PendingPatch {
@@ -297,18 +1869,61 @@ where
             local_file_path,
         }) as Result<PendingPatch>
     }))
-    .buffer_unordered(CONCURRENT_DOWNLOADS)
+    .buffer_unordered(concurrent_downloads)
     .try_collect()
     .await
 }
 
-fn is_archive_valid<P: AsRef<Path>>(archive_path: P) -> Result<bool> {
-    let mut archive = ThorArchive::open(archive_path.as_ref()).context("Failed to open archive")?;
-    match archive.is_valid() {
+/// Checks the integrity of a downloaded patch.
+///
+/// The downloaded file is emptied if it's corrupt, so that it's downloaded
+/// again on the next try instead of being considered complete.
+async fn verify_patch_integrity(
+    patch: &ThorPatchInfo,
+    local_file_path: &Path,
+    tmp_file: &mut File,
+) -> Result<()> {
+    let corrupt_entries = match list_corrupt_entries(local_file_path) {
+        Ok(v) => v,
+        Err(e) => {
+            truncate_file(tmp_file).await?;
+            return Err(e.context(format!(
+                "Failed to check archive's integrity: '{}'",
+                patch.file_name
+            )));
+        }
+    };
+    if !corrupt_entries.is_empty() {
+        truncate_file(tmp_file).await?;
+        let checksum_error = ChecksumError(format!(
+            "checksum mismatch for '{}'",
+            corrupt_entries.join("', '")
+        ));
+        return Err(
+            anyhow::Error::new(checksum_error).context(FileErrorContext::new(
+                format!("Archive '{}' is corrupt", patch.file_name),
+                &patch.file_name,
+            )),
+        );
+    }
+    Ok(())
+}
+
+/// Returns the paths of the entries of the archive whose checksum doesn't match
+/// the one contained in the archive's integrity file.
+///
+/// Archives without an integrity file are considered valid.
+fn list_corrupt_entries<P: AsRef<Path>>(archive_path: P) -> Result<Vec<String>> {
+    // Note: RGZ archives don't contain any integrity file
+    if is_rgz_patch(archive_path.as_ref()) {
+        return Ok(vec![]);
+    }
+    let mut archive = open_thor_archive(archive_path.as_ref()).context("Failed to open archive")?;
+    match archive.corrupt_entries() {
         Err(e) => {
             if let GrufError::EntryNotFound = e {
                 // No integrity file present, consider the archive valid
-                Ok(true)
+                Ok(vec![])
             } else {
                 // Only consider this an error if the integrity file was found
                 Err(anyhow!(
@@ -322,41 +1937,140 @@ fn is_archive_valid<P: AsRef<Path>>(archive_path: P) -> Result<bool> {
 }
 
 /// Downloads a single patch described with a `ThorPatchInfo`.
+///
+/// If `tmp_file` already contains the beginning of the patch (from a previous
+/// interrupted download), the download is resumed with an HTTP range request.
+/// If the server doesn't support range requests, the whole file is downloaded
+/// again.
+///
+/// If a `throttle` is given, reads from the response body are paced so that
+/// the download speed doesn't exceed its limit.
 async fn download_patch_to_file<CB: FnMut(u64, u64)>(
     client: &reqwest::Client,
-    patch_url: &Url,
+    patch_file_url: &Url,
     patch: &ThorPatchInfo,
     tmp_file: &mut File,
+    throttle: Option<&BandwidthThrottle>,
+    read_timeout: Option<Duration>,
     mut progress_callback: CB,
 ) -> Result<()> {
-    let patch_file_url = patch_url.join(patch.file_name.as_str()).context(format!(
-        "Invalid file name '{}' given in patch list file",
-        patch.file_name
-    ))?;
-    let mut resp = client
-        .get(patch_file_url)
-        .send()
+    let resume_offset = tmp_file
+        .metadata()
         .await
-        .context(format!("Failed to download file '{}'", patch.file_name))?;
-    if !resp.status().is_success() {
-        return Err(anyhow!(
-            "Patch file '{}' not found on the remote server",
+        .context(format!(
+            "Failed to read metadata of downloaded file '{}'",
             patch.file_name
-        ));
+        ))?
+        .len();
+    let mut request = client.get(patch_file_url.clone());
+    if resume_offset > 0 {
+        log::info!(
+            "Resuming download of '{}' from byte {}",
+            patch.file_name,
+            resume_offset
+        );
+        request = request.header(header::RANGE, format!("bytes={}-", resume_offset));
+    } else {
+        // Note: Encoded content can only be decoded from the beginning
+        request = request.header(header::ACCEPT_ENCODING, ACCEPTED_CONTENT_ENCODINGS);
     }
-    let bytes_to_download = resp.content_length().unwrap_or(0);
-    let mut downloaded_bytes: u64 = 0;
-    while let Some(chunk) = resp
-        .chunk()
+    let mut resp = with_read_timeout(read_timeout, request.send())
+        .await
+        .context(format!("Failed to download file '{}'", patch.file_name))?;
+    let mut downloaded_bytes: u64 = match resp.status() {
+        StatusCode::PARTIAL_CONTENT => resume_offset,
+        StatusCode::RANGE_NOT_SATISFIABLE => {
+            // The file might have been completely downloaded already
+            if content_range_length(&resp) == Some(resume_offset) {
+                log::info!("'{}' has already been downloaded", patch.file_name);
+                return Ok(());
+            }
+            // Local file is bigger than expected, start over on the next try
+            truncate_file(tmp_file).await?;
+            return Err(anyhow!(
+                "Failed to resume download of file '{}'",
+                patch.file_name
+            ));
+        }
+        status if status.is_success() => {
+            // Range requests aren't supported, download the whole file
+            if resume_offset > 0 {
+                truncate_file(tmp_file).await?;
+            }
+            0
+        }
+        StatusCode::FORBIDDEN => {
+            return Err(anyhow::Error::new(AccessDeniedError)
+                .context(format!("Failed to download file '{}'", patch.file_name)));
+        }
+        status => {
+            return Err(anyhow::Error::new(HttpStatusError(status)).context(format!(
+                "Patch file '{}' not found on the remote server",
+                patch.file_name
+            )));
+        }
+    };
+    tmp_file
+        .seek(SeekFrom::Start(downloaded_bytes))
+        .await
+        .context(format!("Failed to download file '{}'", patch.file_name))?;
+    let bytes_to_download = resp.content_length().map(|len| downloaded_bytes + len);
+    // Note: Files are stored decoded, `downloaded_bytes` counts encoded bytes
+    let mut content_decoder = ContentDecoder::from_headers(resp.headers())
+        .context(format!("Failed to download file '{}'", patch.file_name))?;
+    while let Some(chunk) = with_read_timeout(read_timeout, resp.chunk())
         .await
         .context(format!("Failed to download file '{}'", patch.file_name))?
     {
+        let decoded_chunk;
+        let content = match &mut content_decoder {
+            Some(content_decoder) => match content_decoder.decode(&chunk[..]) {
+                Ok(v) => {
+                    decoded_chunk = v;
+                    &decoded_chunk[..]
+                }
+                Err(e) => {
+                    truncate_file(tmp_file).await?;
+                    return Err(anyhow::Error::new(e)
+                        .context(format!("Failed to decode file '{}'", patch.file_name)));
+                }
+            },
+            None => &chunk[..],
+        };
         tmp_file
-            .write_all(&chunk[..])
+            .write_all(content)
             .await
             .context(format!("Failed to download file '{}'", patch.file_name))?;
         downloaded_bytes += chunk.len() as u64;
-        progress_callback(downloaded_bytes, bytes_to_download);
+        progress_callback(downloaded_bytes, bytes_to_download.unwrap_or(0));
+        if let Some(throttle) = throttle {
+            throttle.consume(chunk.len()).await;
+        }
+    }
+    if let Some(bytes_to_download) = bytes_to_download {
+        if downloaded_bytes != bytes_to_download {
+            // Corrupt download, start over on the next try
+            truncate_file(tmp_file).await?;
+            return Err(anyhow!(
+                "Downloaded file '{}' is corrupt (expected {} bytes, got {})",
+                patch.file_name,
+                bytes_to_download,
+                downloaded_bytes
+            ));
+        }
+    }
+    if let Some(content_decoder) = content_decoder {
+        match content_decoder.finish() {
+            Ok(content) => tmp_file
+                .write_all(&content[..])
+                .await
+                .context(format!("Failed to download file '{}'", patch.file_name))?,
+            Err(e) => {
+                truncate_file(tmp_file).await?;
+                return Err(anyhow::Error::new(e)
+                    .context(format!("Failed to decode file '{}'", patch.file_name)));
+            }
+        }
     }
     tmp_file.sync_all().await.context(format!(
         "Failed to sync downloaded file '{}'",
@@ -365,6 +2079,67 @@ async fn download_patch_to_file<CB: FnMut(u64, u64)>(
     Ok(())
 }
 
+/// Checks the signature of a downloaded patch.
+///
+/// The downloaded file is emptied if its signature is invalid, so that it's
+/// downloaded again on the next try.
+async fn verify_patch_signature(
+    client: &reqwest::Client,
+    verifier: &SignatureVerifier,
+    patch_file_url: &Url,
+    patch: &ThorPatchInfo,
+    local_file_path: &Path,
+    tmp_file: &mut File,
+) -> Result<()> {
+    let signature = fetch_signature(client, patch_file_url)
+        .await
+        .context(format!(
+            "Failed to retrieve the signature of '{}'",
+            patch.file_name
+        ))?;
+    if let Err(e) = verifier.verify_file(local_file_path, &signature) {
+        truncate_file(tmp_file).await?;
+        return Err(e.context(FileErrorContext::new(
+            format!("Signature of patch file '{}' is invalid", patch.file_name),
+            &patch.file_name,
+        )));
+    }
+    Ok(())
+}
+
+/// Returns the complete length of a resource, as indicated in the
+/// 'Content-Range' header of a response (e.g. `bytes */1234`).
+fn content_range_length(resp: &reqwest::Response) -> Option<u64> {
+    resp.headers()
+        .get(header::CONTENT_RANGE)?
+        .to_str()
+        .ok()?
+        .rsplit('/')
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// Empties a file and moves its cursor back to the beginning.
+async fn truncate_file(file: &mut File) -> Result<()> {
+    file.set_len(0).await.context("Failed to truncate file")?;
+    file.seek(SeekFrom::Start(0))
+        .await
+        .context("Failed to truncate file")?;
+    Ok(())
+}
+
+/// Indicates whether applied patches should update the index of the cache file.
+///
+/// Patches of an index range don't update the cache file, unless asked
+/// otherwise.
+fn should_commit_patch_index(config: &PatcherConfiguration) -> bool {
+    match &config.patching.index_range {
+        Some(index_range) => index_range.commit_index,
+        None => true,
+    }
+}
+
 /// Parses and applies a list of patches to GRFs and/or to the game client's
 /// files.
 ///
@@ -376,12 +2151,9 @@ async fn apply_patches<P: AsRef<Path>>(
     ui_controller: &UIController,
     patching_thread_rx: &mut mpsc::Receiver<PatcherCommand>,
 ) -> InterruptibleFnResult<()> {
-    let current_working_dir = env::current_dir().map_err(|e| {
-        InterruptibleFnError::Err(format!(
-            "Failed to resolve current working directory: {}.",
-            e
-        ))
-    })?;
+    let target_directory = get_target_directory(config)
+        .map_err(|e| InterruptibleFnError::Err(e.context("Failed to resolve target directory")))?;
+    let commit_patch_index = should_commit_patch_index(config);
     let patch_count = pending_patch_queue.len();
     ui_controller
         .dispatch_patching_status(PatchingStatus::InstallationInProgress(0, patch_count))
@@ -393,69 +2165,387 @@ async fn apply_patches<P: AsRef<Path>>(
         }
         let patch_name = pending_patch.info.file_name;
         log::info!("Processing {}", patch_name);
-        let mut thor_archive = ThorArchive::open(&pending_patch.local_file_path).map_err(|e| {
-            InterruptibleFnError::Err(format!("Cannot read '{}': {}.", patch_name, e))
-        })?;
-
-        if thor_archive.use_grf_merging() {
-            // Patch GRF file
-            let target_grf_name = {
-                if thor_archive.target_grf_name().is_empty() {
-                    config.client.default_grf_name.clone()
-                } else {
-                    thor_archive.target_grf_name()
-                }
-            };
-            log::trace!("Target GRF: {:?}", target_grf_name);
-            let grf_patching_method = match config.patching.in_place {
-                true => GrfPatchingMethod::InPlace,
-                false => GrfPatchingMethod::OutOfPlace,
-            };
-            let target_grf_path = current_working_dir.join(&target_grf_name);
-            apply_patch_to_grf(
-                grf_patching_method,
-                config.patching.create_grf,
-                target_grf_path,
-                &mut thor_archive,
+        apply_patch_file(
+            &patch_name,
+            &pending_patch.local_file_path,
+            config,
+            &target_directory,
+        )?;
+        // Update the cache file with the last successful patch's index
+        if commit_patch_index {
+            if let Err(e) = write_cache_file(
+                &cache_file_path,
+                PatcherCache {
+                    last_patch_index: pending_patch.info.index,
+                },
             )
-            .map_err(|e| {
-                InterruptibleFnError::Err(format!("Failed to patch '{}': {}.", target_grf_name, e))
-            })?;
-        } else {
-            // Patch root directory
-            apply_patch_to_disk(&current_working_dir, &mut thor_archive).map_err(|e| {
-                InterruptibleFnError::Err(format!("Failed to apply patch '{}': {}.", patch_name, e))
-            })?;
+            .await
+            {
+                log::warn!("Failed to write cache file: {}.", e);
+            }
+            record_patch_checksums(
+                config,
+                pending_patch.info.index,
+                &patch_name,
+                &pending_patch.local_file_path,
+            );
         }
-        // Update the cache file with the last successful patch's index
+        record_applied_patch(config, pending_patch.info.index, patch_name);
+        discard_patch_file(config, &pending_patch.local_file_path);
+        // Update status
+        ui_controller
+            .dispatch_patching_status(PatchingStatus::InstallationInProgress(
+                1 + patch_number,
+                patch_count,
+            ))
+            .await;
+    }
+    Ok(())
+}
+
+/// Reads the downloaded patches and reports what would be applied, without
+/// modifying the game client's files.
+///
+/// The cache file isn't updated either, so that the patches are applied during
+/// the next (non-simulated) run.
+///
+/// This function is interruptible.
+async fn simulate_patches(
+    pending_patch_queue: Vec<PendingPatch>,
+    config: &PatcherConfiguration,
+    ui_controller: &UIController,
+    patching_thread_rx: &mut mpsc::Receiver<PatcherCommand>,
+) -> InterruptibleFnResult<()> {
+    for pending_patch in pending_patch_queue {
+        // Cancel the simulation if we've been asked to
+        if let Some(e) = check_for_cancellation(patching_thread_rx) {
+            return Err(e);
+        }
+        let patch_name = pending_patch.info.file_name;
+        log::info!("Simulating {}", patch_name);
+        let (target, file_count) = simulate_patch_file(&pending_patch.local_file_path, config)
+            .map_err(|e| patch_error(e, format!("Cannot read '{}'", patch_name), &patch_name))?;
+        log::info!(
+            "{} would patch {} file(s) in {:?}",
+            patch_name,
+            file_count,
+            target
+        );
+        ui_controller
+            .dispatch_patching_status(PatchingStatus::PatchSimulated(
+                patch_name, target, file_count,
+            ))
+            .await;
+    }
+    Ok(())
+}
+
+/// Reads a single downloaded patch file, whatever its format.
+///
+/// Returns the name of the GRF file targeted by the patch (`None` for the game
+/// client's directory) and the number of files it would modify.
+fn simulate_patch_file(
+    patch_file_path: &Path,
+    config: &PatcherConfiguration,
+) -> Result<(Option<String>, usize)> {
+    match detect_patch_archive_format(patch_file_path)? {
+        PatchArchiveFormat::Thor => {
+            if !list_corrupt_entries(patch_file_path)?.is_empty() {
+                return Err(anyhow!("Archive contains corrupt entries"));
+            }
+            let mut thor_archive = open_thor_archive(patch_file_path)?;
+            let target_grf_name = patch_target_grf_name(&thor_archive, config);
+            Ok((target_grf_name, simulate_patch(&mut thor_archive)?))
+        }
+        PatchArchiveFormat::Rgz => {
+            let mut rgz_archive = RgzArchive::open(patch_file_path)?;
+            Ok((None, simulate_rgz_patch(&mut rgz_archive)?))
+        }
+    }
+}
+
+/// Compares the game client's files with the latest version of each file
+/// contained in the given patches and applies the patches again if needed.
+///
+/// This function is interruptible.
+async fn repair_from_patches<P: AsRef<Path>>(
+    pending_patch_queue: Vec<PendingPatch>,
+    config: &PatcherConfiguration,
+    cache_file_path: P,
+    ui_controller: &UIController,
+    patching_thread_rx: &mut mpsc::Receiver<PatcherCommand>,
+) -> InterruptibleFnResult<()> {
+    let target_directory = get_target_directory(config)
+        .map_err(|e| InterruptibleFnError::Err(e.context("Failed to resolve target directory")))?;
+    // Find out which patch contains the latest version of each file (files are
+    // identified by their target GRF and their path)
+    let mut latest_patch_per_file: HashMap<(Option<String>, String), usize> = HashMap::new();
+    let mut rgz_patch_numbers = vec![];
+    for (patch_number, pending_patch) in pending_patch_queue.iter().enumerate() {
+        let patch_name = &pending_patch.info.file_name;
+        if is_rgz_patch(&pending_patch.local_file_path) {
+            rgz_patch_numbers.push(patch_number);
+            continue;
+        }
+        let thor_archive = open_thor_archive(&pending_patch.local_file_path)
+            .map_err(|e| patch_error(e, format!("Cannot read '{}'", patch_name), patch_name))?;
+        let target_grf_name = patch_target_grf_name(&thor_archive, config);
+        for entry in thor_archive.get_entries().filter(|e| !e.is_internal()) {
+            latest_patch_per_file.insert(
+                (target_grf_name.clone(), entry.relative_path.clone()),
+                patch_number,
+            );
+        }
+    }
+
+    let patch_count = pending_patch_queue.len();
+    ui_controller
+        .dispatch_patching_status(PatchingStatus::IntegrityCheckInProgress(0, patch_count))
+        .await;
+    for (patch_number, pending_patch) in pending_patch_queue.iter().enumerate() {
+        // Cancel the integrity check if we've been asked to
+        if let Some(e) = check_for_cancellation(patching_thread_rx) {
+            return Err(e);
+        }
+        let patch_name = &pending_patch.info.file_name;
+        log::info!("Verifying {}", patch_name);
+        if rgz_patch_numbers.contains(&patch_number) {
+            // Note: RGZ archives can only be read sequentially, so their
+            // files are extracted again instead of being compared
+            apply_patch_file(
+                patch_name,
+                &pending_patch.local_file_path,
+                config,
+                &target_directory,
+            )?;
+            ui_controller
+                .dispatch_patching_status(PatchingStatus::IntegrityCheckInProgress(
+                    1 + patch_number,
+                    patch_count,
+                ))
+                .await;
+            continue;
+        }
+        let mut thor_archive = open_thor_archive(&pending_patch.local_file_path)
+            .map_err(|e| patch_error(e, format!("Cannot read '{}'", patch_name), patch_name))?;
+        let target_grf_name = patch_target_grf_name(&thor_archive, config);
+        // Only check files that aren't overwritten by later patches
+        let relative_paths: Vec<String> = thor_archive
+            .get_entries()
+            .filter(|e| !e.is_internal())
+            .map(|e| e.relative_path.clone())
+            .filter(|relative_path| {
+                latest_patch_per_file.get(&(target_grf_name.clone(), relative_path.clone()))
+                    == Some(&patch_number)
+            })
+            .collect();
+        let files_match = match &target_grf_name {
+            Some(grf_name) => {
+                get_target_grf_path(config, &target_directory, grf_name).and_then(|grf_path| {
+                    grf_matches_patch(grf_path, &mut thor_archive, &relative_paths)
+                })
+            }
+            None => disk_matches_patch(&target_directory, &mut thor_archive, &relative_paths),
+        }
+        .map_err(|e| patch_error(e, format!("Failed to verify '{}'", patch_name), patch_name))?;
+        if !files_match {
+            log::info!("Files from {} are corrupt, applying it again", patch_name);
+            apply_patch(patch_name, &mut thor_archive, config, &target_directory)?;
+        }
+        // Update status
+        ui_controller
+            .dispatch_patching_status(PatchingStatus::IntegrityCheckInProgress(
+                1 + patch_number,
+                patch_count,
+            ))
+            .await;
+    }
+    for pending_patch in &pending_patch_queue {
+        discard_patch_file(config, &pending_patch.local_file_path);
+    }
+    // All patches are applied at this point, update the cache file accordingly.
+    // Note: The cached index is never lowered, since only some of the applied
+    // patches might have been verified.
+    let last_patch = match pending_patch_queue.last() {
+        Some(v) if should_commit_patch_index(config) => v,
+        _ => return Ok(()),
+    };
+    let is_newer_index = match read_cache_file(&cache_file_path).await {
+        Ok(patcher_cache) => last_patch.info.index > patcher_cache.last_patch_index,
+        Err(_) => true,
+    };
+    if is_newer_index {
         if let Err(e) = write_cache_file(
             &cache_file_path,
             PatcherCache {
-                last_patch_index: pending_patch.info.index,
+                last_patch_index: last_patch.info.index,
             },
         )
         .await
         {
             log::warn!("Failed to write cache file: {}.", e);
         }
-        // Update status
-        ui_controller
-            .dispatch_patching_status(PatchingStatus::InstallationInProgress(
-                1 + patch_number,
-                patch_count,
-            ))
-            .await;
     }
-    Ok(())
+    Ok(())
+}
+
+/// Applies a single downloaded patch file, whatever its format.
+fn apply_patch_file(
+    patch_name: &str,
+    patch_file_path: &Path,
+    config: &PatcherConfiguration,
+    target_directory: &Path,
+) -> InterruptibleFnResult<()> {
+    let archive_format = detect_patch_archive_format(patch_file_path)
+        .map_err(|e| patch_error(e, format!("Cannot read '{}'", patch_name), patch_name))?;
+    match archive_format {
+        PatchArchiveFormat::Thor => {
+            let mut thor_archive = open_thor_archive(patch_file_path)
+                .map_err(|e| patch_error(e, format!("Cannot read '{}'", patch_name), patch_name))?;
+            apply_patch(patch_name, &mut thor_archive, config, target_directory)
+        }
+        PatchArchiveFormat::Rgz => {
+            // Note: RGZ patches always target the game client's directory
+            let mut rgz_archive = RgzArchive::open(patch_file_path)
+                .map_err(|e| patch_error(e, format!("Cannot read '{}'", patch_name), patch_name))?;
+            apply_rgz_patch_to_disk(target_directory, &mut rgz_archive).map_err(|e| {
+                patch_error(
+                    e,
+                    format!("Failed to apply patch '{}'", patch_name),
+                    patch_name,
+                )
+            })
+        }
+    }
+}
+
+/// Returns an error related to the file named `file_name` (e.g., a patch),
+/// reported with the given message.
+fn patch_error<E: Into<anyhow::Error>>(
+    err: E,
+    message: String,
+    file_name: &str,
+) -> InterruptibleFnError {
+    InterruptibleFnError::Err(
+        err.into()
+            .context(FileErrorContext::new(message, file_name)),
+    )
+}
+
+/// Returns `true` if the patch file located at `patch_file_path` is an RGZ
+/// archive.
+fn is_rgz_patch(patch_file_path: &Path) -> bool {
+    match detect_patch_archive_format(patch_file_path) {
+        Ok(archive_format) => archive_format == PatchArchiveFormat::Rgz,
+        Err(_) => false,
+    }
+}
+
+/// Applies a single patch to its target (a GRF file or the game client's
+/// directory).
+fn apply_patch<R: Read + Seek>(
+    patch_name: &str,
+    thor_archive: &mut ThorArchive<R>,
+    config: &PatcherConfiguration,
+    target_directory: &Path,
+) -> InterruptibleFnResult<()> {
+    // Note: Files that already match the patch aren't written again, unless
+    // asked otherwise
+    let skip_unchanged_files = !config.patching.force_apply.unwrap_or(false);
+    if let Some(target_grf_name) = patch_target_grf_name(thor_archive, config) {
+        // Patch GRF file
+        log::trace!("Target GRF: {:?}", target_grf_name);
+        let grf_patching_method = match config.patching.grf_merge_strategy() {
+            GrfMergeStrategy::InPlace => GrfPatchingMethod::InPlace,
+            GrfMergeStrategy::TempSwap => GrfPatchingMethod::OutOfPlace,
+        };
+        let target_grf_path = get_target_grf_path(config, target_directory, &target_grf_name)
+            .map_err(|e| {
+                patch_error(
+                    e,
+                    format!("Failed to stage '{}'", target_grf_name),
+                    &target_grf_name,
+                )
+            })?;
+        apply_patch_to_grf(
+            grf_patching_method,
+            config.patching.create_grf,
+            config.patching.grf_version.unwrap_or(DEFAULT_GRF_VERSION),
+            skip_unchanged_files,
+            config.patching.case_sensitive_paths.unwrap_or(false),
+            target_grf_path,
+            thor_archive,
+        )
+        .map_err(|e| {
+            patch_error(
+                e,
+                format!("Failed to patch '{}'", target_grf_name),
+                &target_grf_name,
+            )
+        })
+    } else {
+        // Patch root directory
+        apply_patch_to_disk(target_directory, skip_unchanged_files, thor_archive).map_err(|e| {
+            patch_error(
+                e,
+                format!("Failed to apply patch '{}'", patch_name),
+                patch_name,
+            )
+        })
+    }
+}
+
+/// Returns the name of the GRF file targeted by a patch, or `None` if the
+/// patch targets the game client's directory.
+fn patch_target_grf_name<R: Read + Seek>(
+    thor_archive: &ThorArchive<R>,
+    config: &PatcherConfiguration,
+) -> Option<String> {
+    // Note: In loose files mode, GRF patches are applied to the game client's
+    // directory (e.g., to an unpacked 'data' directory)
+    if !thor_archive.use_grf_merging() || config.patching.loose_files.unwrap_or(false) {
+        return None;
+    }
+    if thor_archive.target_grf_name().is_empty() {
+        Some(config.client.default_grf_name.clone())
+    } else {
+        Some(thor_archive.target_grf_name())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use httptest::{matchers::*, responders::*, Expectation, Server};
-    use std::io::SeekFrom;
     use tokio::io::AsyncReadExt;
 
+    #[test]
+    fn test_retry_delay() {
+        let base_delay = Duration::from_millis(500);
+        assert_eq!(retry_delay(base_delay, 1), Duration::from_millis(500));
+        assert_eq!(retry_delay(base_delay, 2), Duration::from_millis(1000));
+        assert_eq!(retry_delay(base_delay, 4), Duration::from_millis(4000));
+        // Exponent is capped
+        assert_eq!(retry_delay(base_delay, 100), retry_delay(base_delay, 17));
+        // Delay is capped
+        assert_eq!(
+            retry_delay(Duration::from_secs(1 << 60), 17),
+            MAX_RETRY_DELAY
+        );
+    }
+
+    #[test]
+    fn test_is_permanent_failure() {
+        let err = anyhow::Error::new(HttpStatusError(StatusCode::NOT_FOUND)).context("Not found");
+        assert!(is_permanent_failure(&err));
+        assert!(is_permanent_failure(&anyhow::Error::new(AccessDeniedError)));
+        let err = anyhow::Error::new(HttpStatusError(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(!is_permanent_failure(&err));
+        let err = anyhow::Error::new(HttpStatusError(StatusCode::TOO_MANY_REQUESTS));
+        assert!(!is_permanent_failure(&err));
+        assert!(!is_permanent_failure(&anyhow!("Connection reset")));
+    }
+
     #[tokio::test]
     async fn test_download_path_to_file() {
         // Generate 200MiB of data
@@ -482,9 +2572,11 @@ mod tests {
         let mut tmp_file = File::from_std(tempfile::tempfile().unwrap());
         download_patch_to_file(
             &reqwest::Client::new(),
-            &from_url,
+            &from_url.join(&patch_info.file_name).unwrap(),
             &patch_info,
             &mut tmp_file,
+            None,
+            None,
             |_, _| {},
         )
         .await
@@ -499,4 +2591,586 @@ mod tests {
         // Content check
         assert_eq!(body_content, file_content);
     }
+
+    #[tokio::test]
+    async fn test_download_path_to_file_content_encoding() {
+        use flate2::write::GzEncoder;
+        use std::io::Write;
+
+        let body_content: Vec<u8> = (0..100_000).map(|x| (x % 251) as u8).collect();
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&body_content).unwrap();
+        let encoded_body_content = encoder.finish().unwrap();
+
+        let patch_name = "patch_archive";
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(all_of![
+                request::method_path("GET", format!("/{}", patch_name)),
+                request::headers(contains(("accept-encoding", "gzip, deflate"))),
+            ])
+            .respond_with(
+                status_code(200)
+                    .insert_header("Content-Encoding", "gzip")
+                    .body(encoded_body_content),
+            ),
+        );
+
+        let from_url = Url::parse(server.url("/").to_string().as_str()).unwrap();
+        let patch_info = ThorPatchInfo {
+            index: 0,
+            file_name: patch_name.to_string(),
+        };
+        let mut tmp_file = File::from_std(tempfile::tempfile().unwrap());
+        download_patch_to_file(
+            &reqwest::Client::new(),
+            &from_url.join(&patch_info.file_name).unwrap(),
+            &patch_info,
+            &mut tmp_file,
+            None,
+            None,
+            |_, _| {},
+        )
+        .await
+        .unwrap();
+
+        // Files are stored decoded
+        tmp_file.seek(SeekFrom::Start(0)).await.unwrap();
+        let mut file_content = Vec::new();
+        tmp_file.read_to_end(&mut file_content).await.unwrap();
+        assert_eq!(body_content, file_content);
+    }
+
+    #[tokio::test]
+    async fn test_download_path_to_file_read_timeout() {
+        // Setup a server that accepts connections but never responds
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let server_address = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let _streams: Vec<_> = listener.incoming().collect();
+        });
+
+        let from_url = Url::parse(&format!("http://{}/", server_address)).unwrap();
+        let patch_info = ThorPatchInfo {
+            index: 0,
+            file_name: "patch_archive".to_string(),
+        };
+        let mut tmp_file = File::from_std(tempfile::tempfile().unwrap());
+        let err = download_patch_to_file(
+            &reqwest::Client::new(),
+            &from_url.join(&patch_info.file_name).unwrap(),
+            &patch_info,
+            &mut tmp_file,
+            None,
+            Some(Duration::from_millis(100)),
+            |_, _| {},
+        )
+        .await
+        .unwrap_err();
+        assert!(is_timeout(&err));
+        assert!(!is_timeout(&anyhow!("Connection refused")));
+    }
+
+    #[tokio::test]
+    async fn test_verify_patch_integrity() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let archive_path = temp_dir.path().join("patch.thor.partial");
+        {
+            let archive_file = std::fs::File::create(&archive_path).unwrap();
+            let mut builder =
+                gruf::thor::ThorArchiveBuilder::new(archive_file, true, None).unwrap();
+            builder
+                .append_file_update("data\\file.txt".to_string(), &b"content"[..])
+                .unwrap();
+            builder
+                .append_file_update(
+                    "data.integrity".to_string(),
+                    &b"data\\file.txt=0xDEADBEEF"[..],
+                )
+                .unwrap();
+            builder.finish().unwrap();
+        }
+        let patch_info = ThorPatchInfo {
+            index: 0,
+            file_name: "patch.thor".to_string(),
+        };
+        let mut tmp_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&archive_path)
+            .await
+            .unwrap();
+        let err = verify_patch_integrity(&patch_info, &archive_path, &mut tmp_file)
+            .await
+            .unwrap_err();
+        assert!(err
+            .chain()
+            .any(|cause| cause.downcast_ref::<ChecksumError>().is_some()));
+        // Corrupt archives are downloaded again from scratch
+        assert_eq!(std::fs::metadata(&archive_path).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_patch_checksums() {
+        let config: PatcherConfiguration = serde_yaml::from_str(include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../examples/rpatchur.yml"
+        )))
+        .unwrap();
+        let temp_dir = tempfile::tempdir().unwrap();
+        let archive_path = temp_dir.path().join("patch.thor");
+        {
+            let archive_file = std::fs::File::create(&archive_path).unwrap();
+            let mut builder =
+                gruf::thor::ThorArchiveBuilder::new(archive_file, true, None).unwrap();
+            builder
+                .append_file_update(
+                    "data.integrity".to_string(),
+                    &b"data\\a.txt=0xDEADBEEF\r\ndata\\b.txt=0x12345678"[..],
+                )
+                .unwrap();
+            builder.finish().unwrap();
+        }
+        // Only the checksums of the given files are kept
+        let patch_checksums = patch_checksums(
+            3,
+            "patch.thor",
+            &archive_path,
+            &["data\\a.txt".to_string(), "data\\c.txt".to_string()],
+            &config,
+        )
+        .unwrap();
+        assert_eq!(patch_checksums.patch_index, 3);
+        assert_eq!(
+            patch_checksums.target_grf_name.as_deref(),
+            Some(config.client.default_grf_name.as_str())
+        );
+        assert_eq!(
+            patch_checksums.checksums,
+            vec![("data\\a.txt".to_string(), 0xDEAD_BEEF)]
+                .into_iter()
+                .collect()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_download_path_to_file_access_denied() {
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/patch_archive"))
+                .respond_with(status_code(403)),
+        );
+
+        let from_url = Url::parse(server.url("/").to_string().as_str()).unwrap();
+        let patch_info = ThorPatchInfo {
+            index: 0,
+            file_name: "patch_archive".to_string(),
+        };
+        let mut tmp_file = File::from_std(tempfile::tempfile().unwrap());
+        let err = download_patch_to_file(
+            &reqwest::Client::new(),
+            &from_url.join(&patch_info.file_name).unwrap(),
+            &patch_info,
+            &mut tmp_file,
+            None,
+            None,
+            |_, _| {},
+        )
+        .await
+        .unwrap_err();
+        assert!(is_access_denied(&err));
+        assert!(!is_access_denied(&anyhow!("Patch file not found")));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_run_patching_hook() {
+        let hook = |exit_code: i32| CommandConfiguration {
+            path: "sh".to_string(),
+            arguments: vec!["-c".to_string(), format!("exit {}", exit_code)],
+        };
+        assert!(run_patching_hook("test", &hook(0)).await.is_ok());
+        assert!(run_patching_hook("test", &hook(3)).await.is_err());
+    }
+
+    #[test]
+    fn test_resolve_patch_index() {
+        let patch_list: ThorPatchList = [2, 5, 9]
+            .iter()
+            .map(|index| ThorPatchInfo {
+                index: *index,
+                file_name: format!("{}.thor", index),
+            })
+            .collect();
+        assert_eq!(resolve_patch_index(&patch_list, None).unwrap(), None);
+        assert_eq!(resolve_patch_index(&patch_list, Some(5)).unwrap(), Some(5));
+        assert_eq!(resolve_patch_index(&patch_list, Some(7)).unwrap(), Some(5));
+        assert_eq!(resolve_patch_index(&patch_list, Some(9)).unwrap(), Some(9));
+        assert_eq!(resolve_patch_index(&patch_list, Some(1)).unwrap(), None);
+        assert!(resolve_patch_index(&patch_list, Some(10)).is_err());
+        assert!(resolve_patch_index(&[], Some(0)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_patch_list_if_modified() {
+        let mut server = Server::run();
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/plist.txt")).respond_with(
+                status_code(200)
+                    .insert_header("ETag", "\"v1\"")
+                    .body("1 patch1.thor\n"),
+            ),
+        );
+        let plist_url = Url::parse(server.url("/plist.txt").to_string().as_str()).unwrap();
+        let remote_patch_list = fetch_patch_list(
+            &reqwest::Client::new(),
+            plist_url.clone(),
+            PatchListFormat::Text,
+            None,
+            None,
+            None,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(remote_patch_list.patch_list.len(), 1);
+        assert_eq!(remote_patch_list.etag, Some("\"v1\"".to_string()));
+        server.verify_and_clear();
+
+        server.expect(
+            Expectation::matching(all_of![
+                request::method_path("GET", "/plist.txt"),
+                request::headers(contains(("if-none-match", "\"v1\""))),
+            ])
+            .respond_with(status_code(304)),
+        );
+        let remote_patch_list = fetch_patch_list(
+            &reqwest::Client::new(),
+            plist_url,
+            PatchListFormat::Text,
+            None,
+            None,
+            Some("\"v1\""),
+        )
+        .await
+        .unwrap();
+        assert!(remote_patch_list.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_filter_applied_patches() {
+        let make_patch_list = || -> ThorPatchList {
+            [2, 5, 9]
+                .iter()
+                .map(|index| ThorPatchInfo {
+                    index: *index,
+                    file_name: format!("{}.thor", index),
+                })
+                .collect()
+        };
+        let temp_dir = tempfile::tempdir().unwrap();
+        let cache_file_path = temp_dir.path().join("rpatchur.dat");
+        let indices = |patch_list: &ThorPatchList| -> Vec<usize> {
+            patch_list.iter().map(|patch| patch.index).collect()
+        };
+
+        // Nothing is applied without a cache file
+        let mut patch_list = make_patch_list();
+        let applied_patch_list =
+            filter_applied_patches(&mut patch_list, &cache_file_path, false).await;
+        assert_eq!(indices(&patch_list), vec![2, 5, 9]);
+        assert!(applied_patch_list.is_empty());
+
+        write_cache_file(
+            &cache_file_path,
+            PatcherCache {
+                last_patch_index: 5,
+            },
+        )
+        .await
+        .unwrap();
+        let mut patch_list = make_patch_list();
+        let applied_patch_list =
+            filter_applied_patches(&mut patch_list, &cache_file_path, false).await;
+        assert_eq!(indices(&patch_list), vec![9]);
+        assert_eq!(indices(&applied_patch_list), vec![2, 5]);
+
+        // Gaps in the patch list are tolerated unless the sequence is strict
+        let write_cache_index = |last_patch_index| {
+            write_cache_file(&cache_file_path, PatcherCache { last_patch_index })
+        };
+        write_cache_index(7).await.unwrap();
+        let mut patch_list = make_patch_list();
+        let applied_patch_list =
+            filter_applied_patches(&mut patch_list, &cache_file_path, false).await;
+        assert_eq!(indices(&patch_list), vec![9]);
+        assert_eq!(indices(&applied_patch_list), vec![2, 5]);
+        let mut patch_list = make_patch_list();
+        let applied_patch_list =
+            filter_applied_patches(&mut patch_list, &cache_file_path, true).await;
+        assert_eq!(indices(&patch_list), vec![2, 5, 9]);
+        assert!(applied_patch_list.is_empty());
+
+        // Indices beyond the latest patch are ignored
+        write_cache_index(10).await.unwrap();
+        let mut patch_list = make_patch_list();
+        let applied_patch_list =
+            filter_applied_patches(&mut patch_list, &cache_file_path, false).await;
+        assert_eq!(indices(&patch_list), vec![2, 5, 9]);
+        assert!(applied_patch_list.is_empty());
+    }
+
+    #[test]
+    fn test_required_disk_space() {
+        let mut config: PatcherConfiguration = serde_yaml::from_str(include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../examples/rpatchur.yml"
+        )))
+        .unwrap();
+        let temp_dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            temp_dir.path().join(&config.client.default_grf_name),
+            [0; 100],
+        )
+        .unwrap();
+
+        config.patching.merge_strategy = Some(GrfMergeStrategy::InPlace);
+        assert_eq!(required_disk_space(1000, &config, temp_dir.path()), 2000);
+        // The original GRF is kept until the patched one is complete
+        config.patching.merge_strategy = Some(GrfMergeStrategy::TempSwap);
+        assert_eq!(required_disk_space(1000, &config, temp_dir.path()), 2100);
+        config.client.default_grf_name = "missing.grf".to_string();
+        assert_eq!(required_disk_space(1000, &config, temp_dir.path()), 2000);
+    }
+
+    #[test]
+    fn test_stage_file() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let live_file_path = temp_dir.path().join("data.grf");
+        let staged_file_path = temp_dir.path().join("staging/data.grf");
+        // Nothing to stage
+        stage_file(&live_file_path, &staged_file_path).unwrap();
+        assert!(!staged_file_path.exists());
+
+        std::fs::write(&live_file_path, b"live").unwrap();
+        stage_file(&live_file_path, &staged_file_path).unwrap();
+        assert_eq!(std::fs::read(&staged_file_path).unwrap(), b"live");
+        // Staged files are never overwritten
+        std::fs::write(&staged_file_path, b"staged").unwrap();
+        stage_file(&live_file_path, &staged_file_path).unwrap();
+        assert_eq!(std::fs::read(&staged_file_path).unwrap(), b"staged");
+        assert_eq!(std::fs::read(&live_file_path).unwrap(), b"live");
+    }
+
+    #[tokio::test]
+    async fn test_fetch_patches_total_size() {
+        let server = Server::run();
+        for (patch_name, patch_size) in &[("patch1.thor", 1000), ("patch2.thor", 234)] {
+            server.expect(
+                Expectation::matching(request::method_path("HEAD", format!("/{}", patch_name)))
+                    .respond_with(
+                        status_code(200).insert_header("Content-Length", patch_size.to_string()),
+                    ),
+            );
+        }
+        let mirror_url = Url::parse(server.url("/").to_string().as_str()).unwrap();
+        let mirrors = vec![Mirror {
+            plist_url: mirror_url.join("plist.txt").unwrap(),
+            patch_url: mirror_url,
+            signed_urls: None,
+        }];
+        let patch_list = vec![
+            ThorPatchInfo {
+                index: 0,
+                file_name: "patch1.thor".to_string(),
+            },
+            ThorPatchInfo {
+                index: 1,
+                file_name: "patch2.thor".to_string(),
+            },
+        ];
+        assert_eq!(
+            1234,
+            fetch_patches_total_size(&reqwest::Client::new(), &mirrors, &patch_list)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_patch_size_signed_url() {
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/token"))
+                .times(2)
+                .respond_with(cycle![
+                    status_code(200).body("token1"),
+                    status_code(200).body("token2"),
+                ]),
+        );
+        // The first token is refused
+        server.expect(
+            Expectation::matching(all_of![
+                request::method_path("HEAD", "/patch1.thor"),
+                request::query(url_decoded(contains(("token", "token1")))),
+            ])
+            .respond_with(status_code(403)),
+        );
+        server.expect(
+            Expectation::matching(all_of![
+                request::method_path("HEAD", "/patch1.thor"),
+                request::query(url_decoded(contains(("token", "token2")))),
+            ])
+            .respond_with(status_code(200).insert_header("Content-Length", "1000")),
+        );
+        let mirror_url = Url::parse(server.url("/").to_string().as_str()).unwrap();
+        let template = format!("{}{{file}}?token={{token}}", mirror_url);
+        let token_url = server.url("/token").to_string();
+        let mirror = Mirror {
+            plist_url: mirror_url.join("plist.txt").unwrap(),
+            // Note: Ignored in favor of the template
+            patch_url: mirror_url.join("unused/").unwrap(),
+            signed_urls: Some(SignedUrlBuilder::new(&template, Some(&token_url)).unwrap()),
+        };
+        let patch = ThorPatchInfo {
+            index: 0,
+            file_name: "patch1.thor".to_string(),
+        };
+        assert_eq!(
+            1000,
+            fetch_patch_size(&reqwest::Client::new(), &mirror, &patch)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_download_path_to_file_resume() {
+        let data_size: usize = 1024 * 1024;
+        let body_content: Vec<u8> = (0..data_size).map(|x| x as u8).collect();
+        let resume_offset = data_size / 3;
+
+        let patch_name = "patch_archive";
+        let patch_path = format!("/{}", patch_name);
+        let server = Server::run();
+        // Configure the server to expect a range request and respond with the
+        // rest of the file
+        server.expect(
+            Expectation::matching(all_of![
+                request::method_path("GET", patch_path.clone()),
+                request::headers(contains(("range", format!("bytes={}-", resume_offset)))),
+            ])
+            .respond_with(status_code(206).body(body_content[resume_offset..].to_vec())),
+        );
+
+        let from_url = Url::parse(server.url("/").to_string().as_str()).unwrap();
+        let patch_info = ThorPatchInfo {
+            index: 0,
+            file_name: patch_name.to_string(),
+        };
+        // Simulate an interrupted download
+        let mut tmp_file = File::from_std(tempfile::tempfile().unwrap());
+        tmp_file
+            .write_all(&body_content[..resume_offset])
+            .await
+            .unwrap();
+        download_patch_to_file(
+            &reqwest::Client::new(),
+            &from_url.join(&patch_info.file_name).unwrap(),
+            &patch_info,
+            &mut tmp_file,
+            None,
+            None,
+            |_, _| {},
+        )
+        .await
+        .unwrap();
+
+        tmp_file.seek(SeekFrom::Start(0)).await.unwrap();
+        let mut file_content = Vec::with_capacity(data_size);
+        tmp_file.read_to_end(&mut file_content).await.unwrap();
+        assert_eq!(body_content, file_content);
+    }
+
+    #[tokio::test]
+    async fn test_download_path_to_file_resume_unsupported() {
+        let data_size: usize = 1024 * 1024;
+        let body_content: Vec<u8> = (0..data_size).map(|x| x as u8).collect();
+
+        let patch_name = "patch_archive";
+        let patch_path = format!("/{}", patch_name);
+        let server = Server::run();
+        // Configure the server to ignore range requests
+        server.expect(
+            Expectation::matching(request::method_path("GET", patch_path.clone()))
+                .respond_with(status_code(200).body(body_content.clone())),
+        );
+
+        let from_url = Url::parse(server.url("/").to_string().as_str()).unwrap();
+        let patch_info = ThorPatchInfo {
+            index: 0,
+            file_name: patch_name.to_string(),
+        };
+        // Simulate an interrupted download, with unexpected content
+        let mut tmp_file = File::from_std(tempfile::tempfile().unwrap());
+        tmp_file.write_all(&[0xFFu8; 1000]).await.unwrap();
+        download_patch_to_file(
+            &reqwest::Client::new(),
+            &from_url.join(&patch_info.file_name).unwrap(),
+            &patch_info,
+            &mut tmp_file,
+            None,
+            None,
+            |_, _| {},
+        )
+        .await
+        .unwrap();
+
+        tmp_file.seek(SeekFrom::Start(0)).await.unwrap();
+        let mut file_content = Vec::with_capacity(data_size);
+        tmp_file.read_to_end(&mut file_content).await.unwrap();
+        assert_eq!(data_size as u64, tmp_file.metadata().await.unwrap().len());
+        assert_eq!(body_content, file_content);
+    }
+
+    #[tokio::test]
+    async fn test_download_path_to_file_already_downloaded() {
+        let body_content = vec![0xABu8; 1000];
+        let patch_name = "patch_archive";
+        let patch_path = format!("/{}", patch_name);
+        let server = Server::run();
+        // Configure the server to reject the range request
+        server.expect(
+            Expectation::matching(all_of![
+                request::method_path("GET", patch_path.clone()),
+                request::headers(contains(("range", "bytes=1000-"))),
+            ])
+            .respond_with(status_code(416).insert_header("Content-Range", "bytes */1000")),
+        );
+
+        let from_url = Url::parse(server.url("/").to_string().as_str()).unwrap();
+        let patch_info = ThorPatchInfo {
+            index: 0,
+            file_name: patch_name.to_string(),
+        };
+        // Simulate a completed download (e.g. from a previous run)
+        let mut tmp_file = File::from_std(tempfile::tempfile().unwrap());
+        tmp_file.write_all(&body_content).await.unwrap();
+        download_patch_to_file(
+            &reqwest::Client::new(),
+            &from_url.join(&patch_info.file_name).unwrap(),
+            &patch_info,
+            &mut tmp_file,
+            None,
+            None,
+            |_, _| {},
+        )
+        .await
+        .unwrap();
+
+        tmp_file.seek(SeekFrom::Start(0)).await.unwrap();
+        let mut file_content = Vec::new();
+        tmp_file.read_to_end(&mut file_content).await.unwrap();
+        assert_eq!(body_content, file_content);
+    }
 }
@@ -0,0 +1,222 @@
+use std::fmt;
+use std::io;
+
+use super::core::is_timeout;
+use gruf::GrufError;
+use serde::Serialize;
+
+/// Category of the errors reported to the UI, which allows custom UIs to give
+/// targeted help to players.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorCategory {
+    Network,        // Failed to reach/download from the remote server
+    Checksum,       // Downloaded file doesn't match its checksum or signature
+    DiskFull,       // Not enough space left on the disk
+    Permission,     // Missing permissions to read or write a file
+    CorruptArchive, // Invalid THOR/GRF/RGZ archive
+    Other,
+}
+
+/// Error reported to the UI when a task fails.
+#[derive(Serialize, Debug)]
+pub struct PatchingError {
+    pub category: ErrorCategory,
+    pub message: String,           // Human-readable message
+    pub file_name: Option<String>, // Name of the offending file (e.g., a patch)
+}
+
+impl PatchingError {
+    pub fn new(err: &anyhow::Error) -> Self {
+        Self {
+            category: error_category(err),
+            message: format!("{:#}", err),
+            file_name: err
+                .downcast_ref::<FileErrorContext>()
+                .map(|context| context.file_name.clone()),
+        }
+    }
+}
+
+/// Error indicating that the content of a file doesn't match its expected
+/// checksum or signature.
+#[derive(Debug)]
+pub struct ChecksumError(pub String);
+
+impl fmt::Display for ChecksumError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for ChecksumError {}
+
+/// Error indicating that there isn't enough free space on the disk to patch
+/// the game client.
+#[derive(Debug)]
+pub struct InsufficientDiskSpaceError {
+    pub required_bytes: u64,
+    pub available_bytes: u64,
+}
+
+impl fmt::Display for InsufficientDiskSpaceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const MIB: u64 = 1024 * 1024;
+        write!(
+            f,
+            "Not enough disk space ({} MiB required, {} MiB available)",
+            self.required_bytes / MIB,
+            self.available_bytes / MIB
+        )
+    }
+}
+
+impl std::error::Error for InsufficientDiskSpaceError {}
+
+/// Error indicating that the remote server refused to serve a file (e.g.,
+/// because the token contained in its URL has expired).
+#[derive(Debug)]
+pub struct AccessDeniedError;
+
+impl fmt::Display for AccessDeniedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Access denied by the remote server")
+    }
+}
+
+impl std::error::Error for AccessDeniedError {}
+
+/// Error indicating that the remote server answered a request with an
+/// unexpected status code.
+#[derive(Debug)]
+pub struct HttpStatusError(pub reqwest::StatusCode);
+
+impl fmt::Display for HttpStatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unexpected status code {}", self.0)
+    }
+}
+
+impl std::error::Error for HttpStatusError {}
+
+/// Context given to errors related to a specific file.
+#[derive(Debug)]
+pub struct FileErrorContext {
+    pub message: String,
+    pub file_name: String,
+}
+
+impl FileErrorContext {
+    pub fn new(message: impl Into<String>, file_name: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            file_name: file_name.into(),
+        }
+    }
+}
+
+impl fmt::Display for FileErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Returns the category of the first error of `err`'s chain that can be
+/// categorized.
+fn error_category(err: &anyhow::Error) -> ErrorCategory {
+    if is_timeout(err) {
+        return ErrorCategory::Network;
+    }
+    for cause in err.chain() {
+        if cause.downcast_ref::<ChecksumError>().is_some() {
+            return ErrorCategory::Checksum;
+        }
+        if cause.downcast_ref::<InsufficientDiskSpaceError>().is_some() {
+            return ErrorCategory::DiskFull;
+        }
+        if cause.downcast_ref::<reqwest::Error>().is_some()
+            || cause.downcast_ref::<AccessDeniedError>().is_some()
+            || cause.downcast_ref::<HttpStatusError>().is_some()
+        {
+            return ErrorCategory::Network;
+        }
+        if let Some(io_err) = cause.downcast_ref::<io::Error>() {
+            if io_err.kind() == io::ErrorKind::PermissionDenied {
+                return ErrorCategory::Permission;
+            }
+            if is_disk_full(io_err) {
+                return ErrorCategory::DiskFull;
+            }
+        }
+        match cause.downcast_ref::<GrufError>() {
+            // Note: I/O errors are categorized through their source
+            Some(GrufError::IoError(_)) | None => {}
+            Some(_) => return ErrorCategory::CorruptArchive,
+        }
+    }
+    ErrorCategory::Other
+}
+
+#[cfg(windows)]
+fn is_disk_full(err: &io::Error) -> bool {
+    // ERROR_HANDLE_DISK_FULL or ERROR_DISK_FULL
+    matches!(err.raw_os_error(), Some(39) | Some(112))
+}
+
+#[cfg(not(windows))]
+fn is_disk_full(err: &io::Error) -> bool {
+    // ENOSPC
+    err.raw_os_error() == Some(28)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_patching_error() {
+        let err = anyhow::Error::new(ChecksumError("Checksum mismatch".to_string()))
+            .context(FileErrorContext::new("Archive is corrupt", "patch.thor"))
+            .context("Failed to download patches");
+        let patching_error = PatchingError::new(&err);
+        assert_eq!(patching_error.category, ErrorCategory::Checksum);
+        assert_eq!(
+            patching_error.message,
+            "Failed to download patches: Archive is corrupt: Checksum mismatch"
+        );
+        assert_eq!(patching_error.file_name, Some("patch.thor".to_string()));
+
+        let err = anyhow::Error::new(io::Error::from(io::ErrorKind::PermissionDenied))
+            .context("Failed to open GRF");
+        assert_eq!(PatchingError::new(&err).category, ErrorCategory::Permission);
+        let err = anyhow::Error::new(io::Error::from_raw_os_error(if cfg!(windows) {
+            112
+        } else {
+            28
+        }));
+        assert_eq!(PatchingError::new(&err).category, ErrorCategory::DiskFull);
+        let err = anyhow::Error::new(InsufficientDiskSpaceError {
+            required_bytes: 3 * 1024 * 1024 + 1,
+            available_bytes: 2 * 1024 * 1024,
+        });
+        let patching_error = PatchingError::new(&err);
+        assert_eq!(patching_error.category, ErrorCategory::DiskFull);
+        assert_eq!(
+            patching_error.message,
+            "Not enough disk space (3 MiB required, 2 MiB available)"
+        );
+        let err = anyhow::Error::new(GrufError::IoError(io::Error::from(
+            io::ErrorKind::PermissionDenied,
+        )));
+        assert_eq!(PatchingError::new(&err).category, ErrorCategory::Permission);
+        let err = anyhow::Error::new(GrufError::parsing_error("Invalid header"));
+        assert_eq!(
+            PatchingError::new(&err).category,
+            ErrorCategory::CorruptArchive
+        );
+        let patching_error = PatchingError::new(&anyhow!("Unexpected error"));
+        assert_eq!(patching_error.category, ErrorCategory::Other);
+        assert_eq!(patching_error.file_name, None);
+    }
+}
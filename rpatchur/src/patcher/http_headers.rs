@@ -0,0 +1,131 @@
+use std::env;
+
+use super::config::WebConfiguration;
+use anyhow::{anyhow, Context, Result};
+use reqwest::header::{self, HeaderMap, HeaderName, HeaderValue};
+
+/// Builds the headers sent with every request, as configured in `web`.
+///
+/// Environment variables referenced in header values (e.g., '${TOKEN}') are
+/// substituted, so that secrets don't have to be stored in the configuration
+/// file.
+pub fn default_headers(web: &WebConfiguration) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    if let Some(user_agent) = &web.user_agent {
+        let user_agent = substitute_env_vars(user_agent, |name| env::var(name).ok())?;
+        headers.insert(
+            header::USER_AGENT,
+            HeaderValue::from_str(&user_agent).context("Invalid user agent")?,
+        );
+    }
+    if let Some(custom_headers) = &web.headers {
+        for (name, value) in custom_headers {
+            let header_name = HeaderName::from_bytes(name.as_bytes())
+                .context(format!("Invalid header name: '{}'", name))?;
+            let value = substitute_env_vars(value, |name| env::var(name).ok())
+                .context(format!("Invalid value for header '{}'", name))?;
+            let header_value = HeaderValue::from_str(&value)
+                .context(format!("Invalid value for header '{}'", name))?;
+            headers.insert(header_name, header_value);
+        }
+    }
+    Ok(headers)
+}
+
+/// Replaces the '${NAME}' patterns of `value` with the value returned by
+/// `lookup` for 'NAME'.
+fn substitute_env_vars<F>(value: &str, lookup: F) -> Result<String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut result = String::with_capacity(value.len());
+    let mut remaining = value;
+    while let Some(start) = remaining.find("${") {
+        result.push_str(&remaining[..start]);
+        let end = remaining[start..]
+            .find('}')
+            .ok_or_else(|| anyhow!("Unterminated environment variable reference"))?;
+        let var_name = &remaining[start + 2..start + end];
+        let var_value = lookup(var_name)
+            .ok_or_else(|| anyhow!("Environment variable '{}' is not set", var_name))?;
+        result.push_str(&var_value);
+        remaining = &remaining[start + end + 1..];
+    }
+    result.push_str(remaining);
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::patcher::PatcherConfiguration;
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "CDN_TOKEN" => Some("s3cr3t".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_substitute_env_vars() {
+        assert_eq!(
+            substitute_env_vars("Bearer ${CDN_TOKEN}", lookup).unwrap(),
+            "Bearer s3cr3t"
+        );
+        assert_eq!(
+            substitute_env_vars("${CDN_TOKEN}:${CDN_TOKEN}", lookup).unwrap(),
+            "s3cr3t:s3cr3t"
+        );
+        assert_eq!(
+            substitute_env_vars("no variable $ {}", lookup).unwrap(),
+            "no variable $ {}"
+        );
+        assert!(substitute_env_vars("${UNKNOWN_VAR}", lookup).is_err());
+        assert!(substitute_env_vars("${CDN_TOKEN", lookup).is_err());
+    }
+
+    #[test]
+    fn test_default_headers_example() {
+        // The example configuration must work as shipped
+        let config: PatcherConfiguration = serde_yaml::from_str(include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../examples/rpatchur.yml"
+        )))
+        .unwrap();
+        let headers = default_headers(&config.web).unwrap();
+        assert_eq!(headers["x-patch-channel"], "stable");
+    }
+
+    #[test]
+    fn test_default_headers() {
+        let mut config: PatcherConfiguration = serde_yaml::from_str(include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../examples/rpatchur.yml"
+        )))
+        .unwrap();
+        let web = &mut config.web;
+        web.user_agent = Some("MyServerPatcher/1.0".to_string());
+        web.headers = Some(
+            vec![("X-Patch-Channel".to_string(), "stable".to_string())]
+                .into_iter()
+                .collect(),
+        );
+        let headers = default_headers(web).unwrap();
+        assert_eq!(headers[header::USER_AGENT], "MyServerPatcher/1.0");
+        assert_eq!(headers["x-patch-channel"], "stable");
+
+        web.headers = Some(
+            vec![("Invalid Header".to_string(), "value".to_string())]
+                .into_iter()
+                .collect(),
+        );
+        assert!(default_headers(web).is_err());
+        web.headers = Some(
+            vec![("X-Token".to_string(), "line\nbreak".to_string())]
+                .into_iter()
+                .collect(),
+        );
+        assert!(default_headers(web).is_err());
+    }
+}
@@ -0,0 +1,131 @@
+use std::time::{Duration, Instant};
+
+/// Minimum delay between two consecutive updates of the metrics
+const UPDATE_INTERVAL: Duration = Duration::from_millis(250);
+/// Weight of the latest measurement in the smoothed download speed
+const SMOOTHING_FACTOR: f64 = 0.2;
+
+/// Snapshot of the download metrics, sent periodically to the UI.
+#[derive(Debug, PartialEq)]
+pub struct DownloadMetricsSnapshot {
+    pub downloaded_bytes: u64,
+    pub total_bytes: Option<u64>,
+    pub bytes_per_sec: u64,
+    pub remaining_secs: Option<u64>,
+}
+
+/// Keeps track of the amount of downloaded data and computes a smoothed
+/// download speed (exponential moving average).
+pub struct DownloadMetrics {
+    total_bytes: Option<u64>,
+    downloaded_bytes: u64,
+    last_update: Instant,
+    bytes_since_last_update: u64,
+    average_speed: Option<f64>,
+}
+
+impl DownloadMetrics {
+    pub fn new(total_bytes: Option<u64>) -> Self {
+        Self {
+            total_bytes,
+            downloaded_bytes: 0,
+            last_update: Instant::now(),
+            bytes_since_last_update: 0,
+            average_speed: None,
+        }
+    }
+
+    /// Records `byte_count` newly downloaded bytes.
+    ///
+    /// Returns a snapshot of the metrics if enough time has elapsed since the
+    /// last one, `None` otherwise.
+    pub fn record(&mut self, byte_count: u64) -> Option<DownloadMetricsSnapshot> {
+        self.downloaded_bytes += byte_count;
+        self.bytes_since_last_update += byte_count;
+        let elapsed = self.last_update.elapsed();
+        if elapsed < UPDATE_INTERVAL {
+            return None;
+        }
+        let current_speed = self.bytes_since_last_update as f64 / elapsed.as_secs_f64();
+        let average_speed = smooth_speed(self.average_speed, current_speed);
+        self.average_speed = Some(average_speed);
+        self.last_update = Instant::now();
+        self.bytes_since_last_update = 0;
+        Some(self.snapshot(average_speed))
+    }
+
+    fn snapshot(&self, average_speed: f64) -> DownloadMetricsSnapshot {
+        // Note: Restarted downloads can make us overshoot the total size
+        let downloaded_bytes = match self.total_bytes {
+            Some(total_bytes) => self.downloaded_bytes.min(total_bytes),
+            None => self.downloaded_bytes,
+        };
+        let bytes_per_sec = average_speed.round() as u64;
+        DownloadMetricsSnapshot {
+            downloaded_bytes,
+            total_bytes: self.total_bytes,
+            bytes_per_sec,
+            remaining_secs: self
+                .total_bytes
+                .and_then(|total| estimate_remaining_secs(downloaded_bytes, total, bytes_per_sec)),
+        }
+    }
+}
+
+/// Updates a moving average of the download speed with a new measurement.
+fn smooth_speed(average_speed: Option<f64>, current_speed: f64) -> f64 {
+    match average_speed {
+        None => current_speed,
+        Some(average_speed) => {
+            SMOOTHING_FACTOR * current_speed + (1.0 - SMOOTHING_FACTOR) * average_speed
+        }
+    }
+}
+
+/// Estimates the remaining download time (in seconds), `None` if the download
+/// is stalled.
+fn estimate_remaining_secs(
+    downloaded_bytes: u64,
+    total_bytes: u64,
+    bytes_per_sec: u64,
+) -> Option<u64> {
+    if bytes_per_sec == 0 {
+        return None;
+    }
+    let remaining_bytes = total_bytes.saturating_sub(downloaded_bytes);
+    // Note: Rounded up
+    Some((remaining_bytes as f64 / bytes_per_sec as f64).ceil() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_smooth_speed() {
+        assert_eq!(1000.0, smooth_speed(None, 1000.0));
+        assert_eq!(1200.0, smooth_speed(Some(1000.0), 2000.0));
+        assert_eq!(800.0, smooth_speed(Some(1000.0), 0.0));
+    }
+
+    #[test]
+    fn test_estimate_remaining_secs() {
+        assert_eq!(Some(10), estimate_remaining_secs(0, 1000, 100));
+        assert_eq!(Some(1), estimate_remaining_secs(950, 1000, 100));
+        assert_eq!(Some(0), estimate_remaining_secs(1000, 1000, 100));
+        assert_eq!(None, estimate_remaining_secs(0, 1000, 0));
+    }
+
+    #[test]
+    fn test_record() {
+        let mut metrics = DownloadMetrics::new(Some(1000));
+        // Updates are throttled
+        assert_eq!(None, metrics.record(100));
+        std::thread::sleep(UPDATE_INTERVAL);
+        let snapshot = metrics.record(100).unwrap();
+        assert_eq!(200, snapshot.downloaded_bytes);
+        assert_eq!(Some(1000), snapshot.total_bytes);
+        assert!(snapshot.bytes_per_sec > 0);
+        assert!(snapshot.remaining_secs.is_some());
+    }
+}
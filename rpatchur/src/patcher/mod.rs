@@ -1,19 +1,39 @@
 mod cache;
 mod cancellation;
 mod config;
+mod content_encoding;
 mod core;
+mod error;
+mod http_headers;
+mod metrics;
+mod partial_file;
 mod patching;
+mod pinning;
+mod plist;
+mod self_update;
+mod signature;
+mod signed_url;
+mod tamper;
+mod throttle;
 
 use std::env;
 use std::ffi::OsString;
+use std::path::PathBuf;
 
-pub use self::config::{retrieve_patcher_configuration, PatcherConfiguration};
-pub use self::core::patcher_thread_routine;
+pub use self::cache::read_history_file;
+pub use self::config::{retrieve_patcher_configuration, PatchIndexRange, PatcherConfiguration};
+pub use self::core::{interruptible_patcher_routine, patcher_thread_routine, startup_routine};
+pub use self::error::PatchingError;
 use anyhow::{Context, Result};
 
 pub enum PatcherCommand {
     Start,
-    Cancel, // Canceled by the user
+    ForceStart,                     // Start even if the patch list hasn't changed
+    VerifyIntegrity,                // Check and repair the game client's files
+    QueryPendingPatches,            // Report the number and size of patches to download
+    ClearCache,                     // Remove downloaded patches from the download cache
+    ResetPatchIndex(Option<usize>), // Index of the last patch to consider applied
+    Cancel,                         // Canceled by the user
 }
 
 pub fn get_patcher_name() -> Result<OsString> {
@@ -23,3 +43,34 @@ pub fn get_patcher_name() -> Result<OsString> {
         .context("Current executable path is invalid")?
         .to_os_string())
 }
+
+/// Returns the path of the cache file, which keeps track of the last applied
+/// patch.
+pub fn get_cache_file_path(config: &PatcherConfiguration) -> Result<PathBuf> {
+    let patcher_name = get_patcher_name()?;
+    let cache_file_path = PathBuf::from(patcher_name).with_extension("dat");
+    match &config.patching.staging_data_dir {
+        // Note: Staging installs keep track of their own progress
+        Some(staging_data_dir) => Ok(PathBuf::from(staging_data_dir).join(cache_file_path)),
+        None => Ok(cache_file_path),
+    }
+}
+
+/// Returns the path of the file that contains the history of applied patches,
+/// which is stored next to the cache file.
+pub fn get_history_file_path(config: &PatcherConfiguration) -> Result<PathBuf> {
+    Ok(get_cache_file_path(config)?.with_extension("history.json"))
+}
+
+/// Returns the path of the file that contains the checksums of the last
+/// applied patch used by the tamper check, which is stored next to the cache
+/// file.
+pub fn get_checksums_file_path(config: &PatcherConfiguration) -> Result<PathBuf> {
+    Ok(get_cache_file_path(config)?.with_extension("checksums.json"))
+}
+
+/// Returns the path of the file that contains the validator of the last
+/// applied patch list, which is stored next to the cache file.
+pub fn get_plist_validator_file_path(config: &PatcherConfiguration) -> Result<PathBuf> {
+    Ok(get_cache_file_path(config)?.with_extension("plist.json"))
+}
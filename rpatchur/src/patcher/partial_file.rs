@@ -0,0 +1,186 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Extension appended to the names of files that are being written.
+const PARTIAL_FILE_EXTENSION: &str = ".partial";
+
+/// Returns the path under which `file_path` is written until it's complete
+/// (e.g. 'patch.thor.partial').
+pub fn partial_file_path(file_path: &Path) -> PathBuf {
+    let mut file_name = file_path
+        .file_name()
+        .map(|file_name| file_name.to_os_string())
+        .unwrap_or_default();
+    file_name.push(PARTIAL_FILE_EXTENSION);
+    file_path.with_file_name(file_name)
+}
+
+/// File that's written under a partial name (see `partial_file_path`) and
+/// that only gets its final name once complete, so that interrupted writes
+/// never leave truncated files that look valid.
+///
+/// The partial file is removed if dropped before being persisted.
+pub struct PartialFile {
+    file: Option<fs::File>,
+    partial_path: PathBuf,
+    final_path: PathBuf,
+}
+
+impl PartialFile {
+    pub fn create<P: AsRef<Path>>(final_path: P) -> io::Result<Self> {
+        let final_path = final_path.as_ref().to_path_buf();
+        let partial_path = partial_file_path(&final_path);
+        let file = fs::File::create(&partial_path)?;
+        Ok(Self {
+            file: Some(file),
+            partial_path,
+            final_path,
+        })
+    }
+
+    pub fn as_file(&self) -> &fs::File {
+        self.file
+            .as_ref()
+            .expect("file is only taken when persisted")
+    }
+
+    pub fn as_file_mut(&mut self) -> &mut fs::File {
+        self.file
+            .as_mut()
+            .expect("file is only taken when persisted")
+    }
+
+    /// Closes the file and renames it to its final name, replacing any
+    /// existing file.
+    pub fn persist(mut self) -> io::Result<()> {
+        // Note: Open files cannot be renamed on Windows
+        drop(self.file.take());
+        fs::rename(&self.partial_path, &self.final_path)
+    }
+}
+
+impl Drop for PartialFile {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = fs::remove_file(&self.partial_path);
+        }
+    }
+}
+
+/// Removes the partial files of `directory` that haven't been modified for
+/// longer than `max_age`, as they've been left over by interrupted runs.
+///
+/// Entries that cannot be inspected or removed (e.g., locked files) are
+/// skipped. Returns the number of files removed.
+pub fn remove_stale_partial_files(directory: &Path, max_age: Duration) -> io::Result<usize> {
+    let entries = match fs::read_dir(directory) {
+        Ok(v) => v,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let now = SystemTime::now();
+    let mut removed_files = 0;
+    for entry in entries {
+        let entry = match entry {
+            Ok(v) => v,
+            Err(e) => {
+                log::warn!("Failed to read entry of '{}': {}", directory.display(), e);
+                continue;
+            }
+        };
+        let is_partial = entry
+            .file_name()
+            .to_string_lossy()
+            .ends_with(PARTIAL_FILE_EXTENSION);
+        if !is_partial {
+            continue;
+        }
+        match is_stale_file(&entry, now, max_age) {
+            Ok(false) => {}
+            Ok(true) => {
+                log::info!("Removing stale file '{}'", entry.path().display());
+                match fs::remove_file(entry.path()) {
+                    Ok(()) => removed_files += 1,
+                    Err(e) => {
+                        log::warn!("Failed to remove '{}': {}", entry.path().display(), e)
+                    }
+                }
+            }
+            Err(e) => log::warn!("Failed to inspect '{}': {}", entry.path().display(), e),
+        }
+    }
+    Ok(removed_files)
+}
+
+/// Indicates whether `entry` is a file that hasn't been modified for longer
+/// than `max_age`.
+fn is_stale_file(entry: &fs::DirEntry, now: SystemTime, max_age: Duration) -> io::Result<bool> {
+    let metadata = entry.metadata()?;
+    if !metadata.is_file() {
+        return Ok(false);
+    }
+    // Note: Files modified "in the future" aren't stale
+    let age = now
+        .duration_since(metadata.modified()?)
+        .unwrap_or_else(|_| Duration::from_secs(0));
+    Ok(age > max_age)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_partial_file() {
+        let temp_dir = tempdir().unwrap();
+        let final_path = temp_dir.path().join("my.grf");
+        let partial_path = temp_dir.path().join("my.grf.partial");
+        assert_eq!(partial_file_path(&final_path), partial_path);
+
+        // Dropped before being persisted
+        {
+            let mut partial_file = PartialFile::create(&final_path).unwrap();
+            partial_file.as_file_mut().write_all(b"truncated").unwrap();
+            assert!(partial_path.exists());
+        }
+        assert!(!partial_path.exists());
+        assert!(!final_path.exists());
+
+        let mut partial_file = PartialFile::create(&final_path).unwrap();
+        partial_file.as_file_mut().write_all(b"complete").unwrap();
+        partial_file.persist().unwrap();
+        assert!(!partial_path.exists());
+        assert_eq!(fs::read(&final_path).unwrap(), b"complete");
+    }
+
+    #[test]
+    fn test_remove_stale_partial_files() {
+        let temp_dir = tempdir().unwrap();
+        fs::write(temp_dir.path().join("1.thor.partial"), b"").unwrap();
+        fs::write(temp_dir.path().join("1.thor"), b"").unwrap();
+        fs::create_dir(temp_dir.path().join("dir.partial")).unwrap();
+
+        assert_eq!(
+            remove_stale_partial_files(temp_dir.path(), Duration::from_secs(3600)).unwrap(),
+            0
+        );
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(
+            remove_stale_partial_files(temp_dir.path(), Duration::from_millis(10)).unwrap(),
+            1
+        );
+        assert!(!temp_dir.path().join("1.thor.partial").exists());
+        assert!(temp_dir.path().join("1.thor").exists());
+        assert!(temp_dir.path().join("dir.partial").exists());
+
+        assert_eq!(
+            remove_stale_partial_files(&temp_dir.path().join("missing"), Duration::from_secs(0))
+                .unwrap(),
+            0
+        );
+    }
+}
@@ -1,11 +1,18 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::io::{Read, Seek};
+use std::io::{self, Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
 
-use anyhow::Result;
-use gruf::grf::{GrfArchive, GrfArchiveBuilder};
-use gruf::thor::{ThorArchive, ThorFileEntry};
+use super::partial_file::PartialFile;
+use anyhow::{anyhow, Result};
+use flate2::read::GzDecoder;
+use gruf::grf::{GrfArchive, GrfArchiveBuilder, GrfFileEncryption};
+use gruf::rgz::{self, RgzArchive, RgzEntry};
+use gruf::thor::{self, ThorArchive, ThorFileEntry};
+use gruf::GrufError;
+
+/// Version of the GRF files produced by the patcher by default (2.0).
+pub const DEFAULT_GRF_VERSION: u32 = 0x200;
 
 /// Indicates the method that should be used when patching GRF files.
 pub enum GrfPatchingMethod {
@@ -13,6 +20,13 @@ pub enum GrfPatchingMethod {
     InPlace,
 }
 
+/// Indicates the format of a patch archive.
+#[derive(Debug, PartialEq, Eq)]
+pub enum PatchArchiveFormat {
+    Thor,
+    Rgz,
+}
+
 /// Indicates the type of archive a "file" comes from.
 enum MergeEntrySource {
     GrfArchive,
@@ -24,9 +38,11 @@ enum MergeEntrySource {
 enum DataTransformation {
     None,
     // DecompressZlib,
+    Reencode, // Decrypt and recompress the data
 }
 
 struct MergeEntry {
+    pub relative_path: String,
     pub source: MergeEntrySource,
     pub source_offset: u64,
     pub data_size: usize,
@@ -34,20 +50,70 @@ struct MergeEntry {
 }
 
 /// Patches a GRF file with a THOR archive/patch.
+///
+/// `grf_version` is the version of the resulting GRF (e.g. 0x200). GRFs of a
+/// different version are rewritten in an out-of-place manner to be upgraded
+/// to that version.
+///
+/// If `skip_unchanged_files` is `true`, files whose content already matches
+/// the checksums of the archive aren't written again (in-place patching only).
+///
+/// Unless `case_sensitive_paths` is `true`, the archive's files replace the
+/// GRF's files whose path only differs by its case.
 pub fn apply_patch_to_grf<P: AsRef<Path>, R: Read + Seek>(
     patching_method: GrfPatchingMethod,
     create_if_needed: bool,
+    grf_version: u32,
+    skip_unchanged_files: bool,
+    case_sensitive_paths: bool,
     grf_file_path: P,
     thor_archive: &mut ThorArchive<R>,
 ) -> Result<()> {
+    let (version_major, version_minor) = (grf_version >> 8, grf_version & 0xFF);
+    // Note: Only 2.0 GRFs can be generated by gruf for now
+    if version_major != 2 || version_minor != 0 {
+        return Err(anyhow!("Unsupported GRF version 0x{:x}", grf_version));
+    }
     if !grf_file_path.as_ref().exists() && create_if_needed {
         // Create a new GRF file if needed
         let new_grf = fs::File::create(&grf_file_path)?;
-        GrfArchiveBuilder::create(new_grf, 2, 0)?;
+        GrfArchiveBuilder::create(new_grf, version_major, version_minor)?;
     }
+    let patching_method = match patching_method {
+        GrfPatchingMethod::InPlace => {
+            let grf_archive = GrfArchive::open(grf_file_path.as_ref())?;
+            if grf_archive.version_major() == version_major
+                && grf_archive.version_minor() == version_minor
+            {
+                GrfPatchingMethod::InPlace
+            } else {
+                log::info!(
+                    "Upgrading {} from version {}.{} to {}.{}",
+                    grf_file_path.as_ref().display(),
+                    grf_archive.version_major(),
+                    grf_archive.version_minor(),
+                    version_major,
+                    version_minor
+                );
+                GrfPatchingMethod::OutOfPlace
+            }
+        }
+        GrfPatchingMethod::OutOfPlace => GrfPatchingMethod::OutOfPlace,
+    };
     match patching_method {
-        GrfPatchingMethod::InPlace => apply_patch_to_grf_ip(grf_file_path, thor_archive),
-        GrfPatchingMethod::OutOfPlace => apply_patch_to_grf_oop(grf_file_path, thor_archive),
+        GrfPatchingMethod::InPlace => apply_patch_to_grf_ip(
+            grf_file_path,
+            skip_unchanged_files,
+            case_sensitive_paths,
+            thor_archive,
+        ),
+        GrfPatchingMethod::OutOfPlace => apply_patch_to_grf_oop(
+            grf_file_path,
+            version_major,
+            version_minor,
+            case_sensitive_paths,
+            thor_archive,
+        ),
     }
 }
 
@@ -57,8 +123,38 @@ pub fn apply_patch_to_grf<P: AsRef<Path>, R: Read + Seek>(
 /// case of error.
 fn apply_patch_to_grf_ip<P: AsRef<Path>, R: Read + Seek>(
     grf_file_path: P,
+    skip_unchanged_files: bool,
+    case_sensitive_paths: bool,
     thor_archive: &mut ThorArchive<R>,
 ) -> Result<()> {
+    let mut grf_archive = GrfArchive::open(grf_file_path.as_ref())?;
+    // Paths of the GRF's entries, indexed by their comparison key
+    let grf_paths: HashMap<String, String> = grf_archive
+        .get_entries()
+        .map(|e| {
+            (
+                merge_path_key(&e.relative_path, case_sensitive_paths),
+                e.relative_path.clone(),
+            )
+        })
+        .collect();
+    let grf_path = |relative_path: &str| -> Option<&String> {
+        grf_paths.get(&merge_path_key(relative_path, case_sensitive_paths))
+    };
+    let unchanged_files = if skip_unchanged_files {
+        unchanged_entries(thor_archive, |entry| {
+            let grf_entry_path = grf_path(&entry.relative_path)?;
+            match grf_archive.get_file_entry(grf_entry_path) {
+                Some(grf_entry) if grf_entry.size == entry.size => {
+                    grf_archive.read_file_content(grf_entry_path).ok()
+                }
+                _ => None,
+            }
+        })?
+    } else {
+        HashSet::new()
+    };
+    drop(grf_archive);
     let mut builder = GrfArchiveBuilder::open(grf_file_path)?;
     let mut thor_entries: Vec<ThorFileEntry> = thor_archive
         .get_entries()
@@ -67,6 +163,17 @@ fn apply_patch_to_grf_ip<P: AsRef<Path>, R: Read + Seek>(
         .collect();
     thor_entries.sort_unstable_by(|a, b| a.offset.cmp(&b.offset));
     for entry in thor_entries {
+        if unchanged_files.contains(&entry.relative_path) {
+            log::trace!("Skipping unchanged file '{}'", entry.relative_path);
+            continue;
+        }
+        // Note: Entries whose path only differs by its case are replaced
+        // instead of being duplicated
+        if let Some(grf_entry_path) = grf_path(&entry.relative_path) {
+            if *grf_entry_path != entry.relative_path {
+                let _ = builder.remove_file(grf_entry_path);
+            }
+        }
         if entry.is_removed {
             let _ = builder.remove_file(&entry.relative_path);
         } else {
@@ -78,44 +185,52 @@ fn apply_patch_to_grf_ip<P: AsRef<Path>, R: Read + Seek>(
 
 /// Patches a GRF in an out-of-place manner.
 ///
-/// This is safer and produces output of smaller size but slower.
+/// This is safer and produces output of smaller size but slower. The patched
+/// GRF is built in a temporary file that only replaces the original GRF once
+/// the whole patch has been applied, the original GRF is left untouched in
+/// case of error.
 fn apply_patch_to_grf_oop<P: AsRef<Path>, R: Read + Seek>(
     grf_file_path: P,
+    version_major: u32,
+    version_minor: u32,
+    case_sensitive_paths: bool,
     thor_archive: &mut ThorArchive<R>,
 ) -> Result<()> {
-    // Rename file to back it up
-    let mut backup_file_path = grf_file_path.as_ref().to_path_buf();
-    backup_file_path.set_extension("grf.bak");
-    fs::rename(grf_file_path.as_ref(), &backup_file_path)?;
-
-    // Prepare file entries that'll be used to make the patched GRF
+    // Prepare file entries that'll be used to make the patched GRF, indexed
+    // by the comparison key of their path
     let mut merge_entries: HashMap<String, MergeEntry> = HashMap::new();
-    // Add files from the original archive while discarding files remove in the patch
-    let mut grf_archive = GrfArchive::open(&backup_file_path)?;
+    // Add files from the original archive
+    let mut grf_archive = GrfArchive::open(grf_file_path.as_ref())?;
     for entry in grf_archive.get_entries() {
-        if let Some(e) = thor_archive.get_file_entry(&entry.relative_path) {
-            if e.is_removed {
-                continue;
-            }
-        }
         merge_entries.insert(
-            entry.relative_path.clone(),
+            merge_path_key(&entry.relative_path, case_sensitive_paths),
             MergeEntry {
+                relative_path: entry.relative_path.clone(),
                 source: MergeEntrySource::GrfArchive,
                 source_offset: entry.offset,
                 data_size: entry.size_compressed,
-                transformation: DataTransformation::None,
+                // Encrypted entries (from 1.x GRFs) cannot be copied as is
+                transformation: match entry.encryption {
+                    GrfFileEncryption::Unencrypted => DataTransformation::None,
+                    GrfFileEncryption::Encrypted(_) => DataTransformation::Reencode,
+                },
             },
         );
     }
-    // Add files from the patch
+    // Add files from the patch while discarding files removed in the patch
     for entry in thor_archive.get_entries() {
-        if entry.is_removed || entry.is_internal() {
+        if entry.is_internal() {
+            continue;
+        }
+        let path_key = merge_path_key(&entry.relative_path, case_sensitive_paths);
+        if entry.is_removed {
+            merge_entries.remove(&path_key);
             continue;
         }
         merge_entries.insert(
-            entry.relative_path.clone(),
+            path_key,
             MergeEntry {
+                relative_path: entry.relative_path.clone(),
                 source: MergeEntrySource::ThorArchive,
                 source_offset: entry.offset,
                 data_size: entry.size_compressed,
@@ -124,28 +239,56 @@ fn apply_patch_to_grf_oop<P: AsRef<Path>, R: Read + Seek>(
         );
     }
 
+    // Note: The partial file is created next to the original GRF so that
+    // it can be renamed atomically. It's deleted automatically on error.
+    let mut tmp_grf_file = PartialFile::create(grf_file_path.as_ref())?;
     {
-        let grf_file = fs::File::create(grf_file_path)?;
-        let mut builder = GrfArchiveBuilder::create(grf_file, 2, 0)?;
-        for (relative_path, entry) in merge_entries {
+        let mut builder =
+            GrfArchiveBuilder::create(tmp_grf_file.as_file_mut(), version_major, version_minor)?;
+        for (_, entry) in merge_entries {
+            let relative_path = entry.relative_path;
             match entry.source {
-                MergeEntrySource::GrfArchive => {
-                    builder.import_raw_entry_from_grf(&mut grf_archive, relative_path)?;
-                }
+                MergeEntrySource::GrfArchive => match entry.transformation {
+                    DataTransformation::None => {
+                        builder.import_raw_entry_from_grf(&mut grf_archive, relative_path)?;
+                    }
+                    DataTransformation::Reencode => {
+                        let content = grf_archive.read_file_content(&relative_path)?;
+                        builder.add_file(relative_path, content.as_slice())?;
+                    }
+                },
                 MergeEntrySource::ThorArchive => {
                     builder.import_raw_entry_from_thor(thor_archive, relative_path)?;
                 }
             }
         }
+        builder.finish()?;
+    }
+    tmp_grf_file.as_file().sync_all()?;
+    // Close the original GRF before replacing it
+    drop(grf_archive);
+    tmp_grf_file.persist()?;
+    Ok(())
+}
+
+/// Returns the key used to compare the paths of the entries merged into a
+/// GRF.
+fn merge_path_key(relative_path: &str, case_sensitive_paths: bool) -> String {
+    if case_sensitive_paths {
+        relative_path.to_string()
+    } else {
+        relative_path.to_lowercase()
     }
-    // Remove backup file once the patched GRF has been built
-    Ok(fs::remove_file(backup_file_path)?)
 }
 
 /// Patches files located in the game client's directory with a THOR
 /// archive/patch.
+///
+/// If `skip_unchanged_files` is `true`, files whose content already matches
+/// the checksums of the archive aren't written again.
 pub fn apply_patch_to_disk<P: AsRef<Path>, R: Read + Seek>(
     root_directory: P,
+    skip_unchanged_files: bool,
     thor_archive: &mut ThorArchive<R>,
 ) -> Result<()> {
     // TODO(LinkZ): Save original files before updating/removing them in order
@@ -157,11 +300,29 @@ pub fn apply_patch_to_disk<P: AsRef<Path>, R: Read + Seek>(
         .cloned()
         .collect();
     file_entries.sort_unstable_by(|a, b| a.offset.cmp(&b.offset));
-    for entry in file_entries {
-        let dest_path = join_windows_relative_path(root_directory.as_ref(), &entry.relative_path);
+    let unchanged_files = if skip_unchanged_files {
+        unchanged_entries(thor_archive, |entry| {
+            let file_path =
+                join_windows_relative_path(root_directory.as_ref(), &entry.relative_path).ok()?;
+            match fs::metadata(&file_path) {
+                Ok(metadata) if metadata.len() == entry.size as u64 => fs::read(file_path).ok(),
+                _ => None,
+            }
+        })?
+    } else {
+        HashSet::new()
+    };
+    // Note: Paths are validated before modifying any file
+    let dest_paths = file_entries
+        .iter()
+        .map(|entry| join_windows_relative_path(root_directory.as_ref(), &entry.relative_path))
+        .collect::<Result<Vec<PathBuf>>>()?;
+    for (entry, dest_path) in file_entries.into_iter().zip(dest_paths) {
         if entry.is_removed {
             // Try to remove file and ignore errors (file might not exist)
             let _ignore = fs::remove_file(dest_path);
+        } else if unchanged_files.contains(&entry.relative_path) {
+            log::trace!("Skipping unchanged file '{}'", entry.relative_path);
         } else {
             // Create parent directory if needed
             if let Some(parent_dir) = dest_path.parent() {
@@ -174,19 +335,243 @@ pub fn apply_patch_to_disk<P: AsRef<Path>, R: Read + Seek>(
     Ok(())
 }
 
+/// Returns the paths of the entries of a THOR archive/patch whose current
+/// content (as returned by `read_current_content`) matches the checksums
+/// contained in the archive's integrity file.
+///
+/// Archives without an integrity file don't have any unchanged entry.
+fn unchanged_entries<R, F>(
+    thor_archive: &mut ThorArchive<R>,
+    mut read_current_content: F,
+) -> Result<HashSet<String>>
+where
+    R: Read + Seek,
+    F: FnMut(&ThorFileEntry) -> Option<Vec<u8>>,
+{
+    let checksums = match thor_archive.checksums() {
+        Ok(v) => v,
+        Err(GrufError::EntryNotFound) => return Ok(HashSet::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let unchanged_entries: HashSet<String> = thor_archive
+        .get_entries()
+        .filter(|e| !e.is_internal() && !e.is_removed)
+        .filter(|e| match checksums.get(&e.relative_path) {
+            Some(expected_checksum) => match read_current_content(e) {
+                Some(content) => thor::checksum(&content) == *expected_checksum,
+                None => false,
+            },
+            None => false,
+        })
+        .map(|e| e.relative_path.clone())
+        .collect();
+    if !unchanged_entries.is_empty() {
+        log::info!("Skipping {} unchanged file(s)", unchanged_entries.len());
+    }
+    Ok(unchanged_entries)
+}
+
+/// Checks that the given entries of a THOR archive/patch are present, with
+/// the same content, in a GRF file.
+///
+/// Entries removed by the patch must be absent from the GRF.
+pub fn grf_matches_patch<P: AsRef<Path>, R: Read + Seek>(
+    grf_file_path: P,
+    thor_archive: &mut ThorArchive<R>,
+    relative_paths: &[String],
+) -> Result<bool> {
+    if !grf_file_path.as_ref().exists() {
+        return Ok(false);
+    }
+    let mut grf_archive = GrfArchive::open(grf_file_path)?;
+    for relative_path in relative_paths {
+        let is_removed = match thor_archive.get_file_entry(relative_path) {
+            Some(entry) => entry.is_removed,
+            None => continue,
+        };
+        if is_removed {
+            if grf_archive.contains_file(relative_path) {
+                return Ok(false);
+            }
+        } else {
+            if !grf_archive.contains_file(relative_path) {
+                return Ok(false);
+            }
+            let expected_content = thor_archive.read_file_content(relative_path)?;
+            if grf_archive.read_file_content(relative_path)? != expected_content {
+                return Ok(false);
+            }
+        }
+    }
+    Ok(true)
+}
+
+/// Checks that the given entries of a THOR archive/patch are present, with
+/// the same content, in the game client's directory.
+///
+/// Entries removed by the patch must be absent from the directory.
+pub fn disk_matches_patch<P: AsRef<Path>, R: Read + Seek>(
+    root_directory: P,
+    thor_archive: &mut ThorArchive<R>,
+    relative_paths: &[String],
+) -> Result<bool> {
+    for relative_path in relative_paths {
+        let is_removed = match thor_archive.get_file_entry(relative_path) {
+            Some(entry) => entry.is_removed,
+            None => continue,
+        };
+        let file_path = join_windows_relative_path(root_directory.as_ref(), relative_path)?;
+        if is_removed {
+            if file_path.exists() {
+                return Ok(false);
+            }
+        } else {
+            if !file_path.is_file() {
+                return Ok(false);
+            }
+            let expected_content = thor_archive.read_file_content(relative_path)?;
+            if fs::read(file_path)? != expected_content {
+                return Ok(false);
+            }
+        }
+    }
+    Ok(true)
+}
+
+/// Detects the format of the patch archive located at `archive_path` from its
+/// magic bytes.
+///
+/// Archives that aren't recognized are considered to be THOR archives.
+pub fn detect_patch_archive_format<P: AsRef<Path>>(archive_path: P) -> Result<PatchArchiveFormat> {
+    let mut header = Vec::with_capacity(2);
+    fs::File::open(archive_path.as_ref())?
+        .take(2)
+        .read_to_end(&mut header)?;
+    // Note: THOR archives compressed as a whole are gzip streams too
+    if rgz::is_rgz_header(&header) && !is_compressed_thor_archive(archive_path)? {
+        Ok(PatchArchiveFormat::Rgz)
+    } else {
+        Ok(PatchArchiveFormat::Thor)
+    }
+}
+
+/// Returns `true` if the archive located at `archive_path` is a THOR archive
+/// compressed as a whole with gzip (e.g., 'patch.thor.gz').
+fn is_compressed_thor_archive<P: AsRef<Path>>(archive_path: P) -> Result<bool> {
+    let mut header = Vec::with_capacity(thor::THOR_HEADER_MAGIC_SIZE);
+    let archive_file = fs::File::open(archive_path)?;
+    // Note: Streams that cannot be decompressed aren't compressed archives,
+    // errors are reported when reading them
+    let decompressed = GzDecoder::new(archive_file)
+        .take(thor::THOR_HEADER_MAGIC_SIZE as u64)
+        .read_to_end(&mut header);
+    Ok(decompressed.is_ok() && thor::is_thor_header(&header))
+}
+
+/// Opens the THOR archive located at `archive_path`.
+///
+/// THOR archives compressed as a whole are transparently decompressed to a
+/// temporary file first.
+pub fn open_thor_archive<P: AsRef<Path>>(archive_path: P) -> Result<ThorArchive<fs::File>> {
+    let mut archive_file = fs::File::open(archive_path.as_ref())?;
+    if is_compressed_thor_archive(archive_path)? {
+        let mut decompressed_file = tempfile::tempfile()?;
+        io::copy(&mut GzDecoder::new(archive_file), &mut decompressed_file)?;
+        decompressed_file.seek(SeekFrom::Start(0))?;
+        archive_file = decompressed_file;
+    }
+    Ok(ThorArchive::new(archive_file)?)
+}
+
+/// Extracts the entries of an RGZ archive/patch into the game client's
+/// directory.
+pub fn apply_rgz_patch_to_disk<P: AsRef<Path>, R: Read>(
+    root_directory: P,
+    rgz_archive: &mut RgzArchive<R>,
+) -> Result<()> {
+    for entry in rgz_archive {
+        match entry? {
+            RgzEntry::Directory { relative_path } => {
+                let dest_path =
+                    join_windows_relative_path(root_directory.as_ref(), &relative_path)?;
+                fs::create_dir_all(dest_path)?;
+            }
+            RgzEntry::File {
+                relative_path,
+                content,
+            } => {
+                let dest_path =
+                    join_windows_relative_path(root_directory.as_ref(), &relative_path)?;
+                // Create parent directory if needed
+                if let Some(parent_dir) = dest_path.parent() {
+                    fs::create_dir_all(parent_dir)?
+                }
+                fs::write(dest_path, content)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reads all the entries of a THOR archive, without applying it.
+///
+/// Returns the number of files the patch would add, update or remove.
+pub fn simulate_patch<R: Read + Seek>(thor_archive: &mut ThorArchive<R>) -> Result<usize> {
+    let entries: Vec<ThorFileEntry> = thor_archive
+        .get_entries()
+        .filter(|e| !e.is_internal())
+        .cloned()
+        .collect();
+    for entry in entries.iter().filter(|e| !e.is_removed) {
+        // Note: Decompressing the content checks that the entry is readable
+        thor_archive
+            .read_file_content(&entry.relative_path)
+            .map_err(|e| anyhow!("Cannot read '{}': {}", entry.relative_path, e))?;
+    }
+    Ok(entries.len())
+}
+
+/// Reads all the entries of an RGZ archive, without applying it.
+///
+/// Returns the number of files the patch would add or update.
+pub fn simulate_rgz_patch<R: Read>(rgz_archive: &mut RgzArchive<R>) -> Result<usize> {
+    let mut file_count = 0;
+    for entry in rgz_archive {
+        if let RgzEntry::File { .. } = entry? {
+            file_count += 1;
+        }
+    }
+    Ok(file_count)
+}
+
 /// Utility function used to join path-like segments the same way it's done in
 /// the GRF file format (Windows style).
-fn join_windows_relative_path(path: &Path, windows_relative_path: &str) -> PathBuf {
+///
+/// Paths that would escape `path` (e.g., containing '..' or a drive letter)
+/// are rejected.
+pub fn join_windows_relative_path(path: &Path, windows_relative_path: &str) -> Result<PathBuf> {
     let mut result = PathBuf::from(path);
-    for component in windows_relative_path.split('\\') {
-        result.push(component);
+    for component in windows_relative_path.split(&['\\', '/'][..]) {
+        match component {
+            "" | "." => continue,
+            ".." => return Err(anyhow!("Invalid path '{}'", windows_relative_path)),
+            _ if component.contains(':') => {
+                return Err(anyhow!("Invalid path '{}'", windows_relative_path))
+            }
+            _ => result.push(component),
+        }
     }
-    result
+    Ok(result)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use gruf::thor::ThorArchiveBuilder;
+    use std::cell::Cell;
+    use std::rc::Rc;
     use tempfile::tempdir;
     use walkdir::WalkDir;
 
@@ -214,7 +599,7 @@ mod tests {
             assert!(!expected_file_path.exists());
             assert_eq!(0, count_files(temp_dir.path()));
 
-            apply_patch_to_disk(temp_dir.path(), &mut thor_archive).unwrap();
+            apply_patch_to_disk(temp_dir.path(), false, &mut thor_archive).unwrap();
 
             // After patching
             assert!(expected_file_path.exists());
@@ -223,6 +608,136 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_join_windows_relative_path() {
+        let root = Path::new("game");
+        assert_eq!(
+            join_windows_relative_path(root, "data\\texture\\a.bmp").unwrap(),
+            root.join("data").join("texture").join("a.bmp")
+        );
+        assert_eq!(
+            join_windows_relative_path(root, "\\data/.\\a.bmp").unwrap(),
+            root.join("data").join("a.bmp")
+        );
+        assert!(join_windows_relative_path(root, "data\\..\\..\\a.bmp").is_err());
+        assert!(join_windows_relative_path(root, "../a.bmp").is_err());
+        assert!(join_windows_relative_path(root, "C:\\Windows\\a.dll").is_err());
+    }
+
+    #[test]
+    fn test_apply_patch_to_disk_path_traversal() {
+        let temp_dir = tempdir().unwrap();
+        let thor_archive_path = temp_dir.path().join("traversal.thor");
+        {
+            let output_file = fs::File::create(&thor_archive_path).unwrap();
+            let mut builder = ThorArchiveBuilder::new(output_file, false, None).unwrap();
+            builder
+                .append_file_update("data\\a.txt".to_string(), &b"a"[..])
+                .unwrap();
+            builder
+                .append_file_update("..\\evil.txt".to_string(), &b"evil"[..])
+                .unwrap();
+            builder.finish().unwrap();
+        }
+        let game_directory = temp_dir.path().join("game");
+        let mut thor_archive = ThorArchive::open(&thor_archive_path).unwrap();
+        assert!(apply_patch_to_disk(&game_directory, false, &mut thor_archive).is_err());
+        // Nothing is written
+        assert!(!temp_dir.path().join("evil.txt").exists());
+        assert!(!game_directory.join("data").exists());
+    }
+
+    #[test]
+    fn test_open_compressed_thor_archive() {
+        let thor_dir_path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources/tests/thor");
+        let temp_dir = tempdir().unwrap();
+        let thor_archive_path = temp_dir.path().join("small.thor.gz");
+        {
+            let output_file = fs::File::create(&thor_archive_path).unwrap();
+            let mut encoder = GzEncoder::new(output_file, Compression::default());
+            io::copy(
+                &mut fs::File::open(thor_dir_path.join("small.thor")).unwrap(),
+                &mut encoder,
+            )
+            .unwrap();
+            encoder.finish().unwrap();
+        }
+        assert_eq!(
+            detect_patch_archive_format(&thor_archive_path).unwrap(),
+            PatchArchiveFormat::Thor
+        );
+
+        let mut thor_archive = open_thor_archive(&thor_archive_path).unwrap();
+        let mut expected_thor_archive =
+            ThorArchive::open(&thor_dir_path.join("small.thor")).unwrap();
+        assert_eq!(
+            thor_archive.file_count(),
+            expected_thor_archive.file_count()
+        );
+        let relative_paths: Vec<String> = expected_thor_archive
+            .get_entries()
+            .map(|e| e.relative_path.clone())
+            .collect();
+        for relative_path in relative_paths {
+            assert_eq!(
+                thor_archive.read_file_content(&relative_path).unwrap(),
+                expected_thor_archive
+                    .read_file_content(&relative_path)
+                    .unwrap()
+            );
+        }
+        // Uncompressed archives are read as is
+        assert!(open_thor_archive(thor_dir_path.join("small.thor")).is_ok());
+    }
+
+    #[test]
+    fn test_apply_rgz_patch_to_disk() {
+        let rgz_dir_path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources/tests/rgz");
+        let thor_dir_path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources/tests/thor");
+        let temp_dir = tempdir().unwrap();
+        let rgz_archive_path = rgz_dir_path.join("small.rgz");
+        assert_eq!(
+            detect_patch_archive_format(&rgz_archive_path).unwrap(),
+            PatchArchiveFormat::Rgz
+        );
+        assert_eq!(
+            detect_patch_archive_format(thor_dir_path.join("small.thor")).unwrap(),
+            PatchArchiveFormat::Thor
+        );
+
+        let mut rgz_archive = RgzArchive::open(&rgz_archive_path).unwrap();
+        apply_rgz_patch_to_disk(temp_dir.path(), &mut rgz_archive).unwrap();
+        assert!(temp_dir.path().join("data/luafiles514").is_dir());
+        assert_eq!(
+            fs::read(temp_dir.path().join("data/clientinfo.xml")).unwrap(),
+            b"<?xml version=\"1.0\"?><clientinfo/>"
+        );
+        assert_eq!(
+            fs::read(temp_dir.path().join("readme.txt")).unwrap(),
+            b"Hello from an RGZ patch\r\n"
+        );
+        let file_count = WalkDir::new(temp_dir.path())
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|entry| entry.file_type().is_file())
+            .count();
+        assert_eq!(2, file_count);
+    }
+
+    #[test]
+    fn test_simulate_patch() {
+        let resources_path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources/tests");
+        let mut thor_archive = ThorArchive::open(&resources_path.join("thor/small.thor")).unwrap();
+        let nb_of_added_files = thor_archive.file_count() - 1;
+        assert_eq!(
+            simulate_patch(&mut thor_archive).unwrap(),
+            nb_of_added_files
+        );
+
+        let mut rgz_archive = RgzArchive::open(resources_path.join("rgz/small.rgz")).unwrap();
+        assert!(simulate_rgz_patch(&mut rgz_archive).unwrap() > 0);
+    }
+
     #[test]
     fn test_apply_patch_to_grf_ip_empty() {
         let grf_dir_path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources/tests/grf");
@@ -244,6 +759,9 @@ mod tests {
             apply_patch_to_grf(
                 GrfPatchingMethod::InPlace,
                 false,
+                DEFAULT_GRF_VERSION,
+                false,
+                false,
                 &grf_archive_path,
                 &mut thor_archive,
             )
@@ -270,6 +788,9 @@ mod tests {
             apply_patch_to_grf(
                 GrfPatchingMethod::InPlace,
                 true,
+                DEFAULT_GRF_VERSION,
+                false,
+                false,
                 &grf_archive_path,
                 &mut thor_archive,
             )
@@ -305,6 +826,9 @@ mod tests {
             apply_patch_to_grf(
                 GrfPatchingMethod::OutOfPlace,
                 false,
+                DEFAULT_GRF_VERSION,
+                false,
+                false,
                 &grf_archive_path,
                 &mut thor_archive,
             )
@@ -331,6 +855,9 @@ mod tests {
             apply_patch_to_grf(
                 GrfPatchingMethod::OutOfPlace,
                 true,
+                DEFAULT_GRF_VERSION,
+                false,
+                false,
                 &grf_archive_path,
                 &mut thor_archive,
             )
@@ -345,6 +872,315 @@ mod tests {
         assert!(patch_maintained_integrity(&thor_archive_path, &grf_archive_path).unwrap());
     }
 
+    #[test]
+    fn test_apply_patch_to_grf_upgrade_version() {
+        let grf_dir_path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources/tests/grf");
+        let thor_dir_path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources/tests/thor");
+        let temp_dir = tempdir().unwrap();
+        let thor_archive_path = thor_dir_path.join("small.thor");
+        let grf_archive_path = temp_dir.path().join("small.grf");
+        {
+            fs::copy(grf_dir_path.join("103-small.grf"), &grf_archive_path).unwrap();
+
+            // Before patching
+            let mut grf_archive = GrfArchive::open(&grf_archive_path).unwrap();
+            assert_eq!(grf_archive.version_major(), 1);
+            let grf_entries: Vec<String> = grf_archive
+                .get_entries()
+                .map(|e| e.relative_path.clone())
+                .collect();
+            let mut grf_contents = HashMap::new();
+            for relative_path in grf_entries {
+                let content = grf_archive.read_file_content(&relative_path).unwrap();
+                grf_contents.insert(relative_path, content);
+            }
+
+            let mut thor_archive = ThorArchive::open(&thor_archive_path).unwrap();
+            apply_patch_to_grf(
+                GrfPatchingMethod::InPlace,
+                false,
+                DEFAULT_GRF_VERSION,
+                false,
+                false,
+                &grf_archive_path,
+                &mut thor_archive,
+            )
+            .unwrap();
+
+            // After patching
+            let mut grf_archive = GrfArchive::open(&grf_archive_path).unwrap();
+            assert_eq!(grf_archive.version_major(), 2);
+            assert_eq!(grf_archive.version_minor(), 0);
+            for (relative_path, content) in grf_contents {
+                if thor_archive.get_file_entry(&relative_path).is_some() {
+                    continue;
+                }
+                assert_eq!(
+                    grf_archive.read_file_content(&relative_path).unwrap(),
+                    content
+                );
+            }
+        }
+        assert!(patch_maintained_integrity(&thor_archive_path, &grf_archive_path).unwrap());
+    }
+
+    #[test]
+    fn test_apply_patch_to_grf_unsupported_version() {
+        let thor_dir_path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources/tests/thor");
+        let temp_dir = tempdir().unwrap();
+        let thor_archive_path = thor_dir_path.join("small.thor");
+        let grf_archive_path = temp_dir.path().join("empty.grf");
+
+        let mut thor_archive = ThorArchive::open(&thor_archive_path).unwrap();
+        assert!(apply_patch_to_grf(
+            GrfPatchingMethod::OutOfPlace,
+            true,
+            0x103,
+            false,
+            false,
+            &grf_archive_path,
+            &mut thor_archive,
+        )
+        .is_err());
+        assert!(!grf_archive_path.exists());
+    }
+
+    /// Reader that fails once `fail` is set, to interrupt patches partway.
+    struct FailingReader {
+        inner: fs::File,
+        fail: Rc<Cell<bool>>,
+    }
+
+    impl Read for FailingReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.fail.get() {
+                return Err(io::Error::from(io::ErrorKind::Other));
+            }
+            self.inner.read(buf)
+        }
+    }
+
+    impl Seek for FailingReader {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    #[test]
+    fn test_apply_patch_to_grf_oop_rollback() {
+        let resources_path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources/tests");
+        let temp_dir = tempdir().unwrap();
+        let grf_archive_path = temp_dir.path().join("200-small.grf");
+        fs::copy(resources_path.join("grf/200-small.grf"), &grf_archive_path).unwrap();
+        let grf_content = fs::read(&grf_archive_path).unwrap();
+
+        // Reading the patch's entries fails once the merge has started
+        let fail = Rc::new(Cell::new(false));
+        let mut thor_archive = ThorArchive::new(FailingReader {
+            inner: fs::File::open(resources_path.join("thor/small.thor")).unwrap(),
+            fail: fail.clone(),
+        })
+        .unwrap();
+        fail.set(true);
+        assert!(apply_patch_to_grf(
+            GrfPatchingMethod::OutOfPlace,
+            false,
+            DEFAULT_GRF_VERSION,
+            false,
+            false,
+            &grf_archive_path,
+            &mut thor_archive,
+        )
+        .is_err());
+
+        // The original file must be left untouched, without leftovers
+        assert_eq!(fs::read(&grf_archive_path).unwrap(), grf_content);
+        assert!(!temp_dir.path().join("200-small.grf.partial").exists());
+        assert_eq!(1, fs::read_dir(temp_dir.path()).unwrap().count());
+    }
+
+    #[test]
+    fn test_apply_patch_to_grf_case_insensitive_paths() {
+        let temp_dir = tempdir().unwrap();
+        let thor_archive_path = temp_dir.path().join("update.thor");
+        {
+            let output_file = fs::File::create(&thor_archive_path).unwrap();
+            let mut builder = ThorArchiveBuilder::new(output_file, true, None).unwrap();
+            builder
+                .append_file_update("data\\texture\\a.bmp".to_string(), &b"new"[..])
+                .unwrap();
+            builder.append_file_removal("DATA\\B.TXT".to_string());
+            builder.finish().unwrap();
+        }
+        let make_grf = |grf_archive_path: &Path| {
+            let grf_file = fs::File::create(grf_archive_path).unwrap();
+            let mut builder = GrfArchiveBuilder::create(grf_file, 2, 0).unwrap();
+            builder
+                .add_file("data\\Texture\\a.bmp".to_string(), &b"old"[..])
+                .unwrap();
+            builder
+                .add_file("data\\b.txt".to_string(), &b"b"[..])
+                .unwrap();
+            builder.finish().unwrap();
+        };
+        let patch_grf = |patching_method, case_sensitive_paths, grf_archive_path: &Path| {
+            make_grf(grf_archive_path);
+            let mut thor_archive = ThorArchive::open(&thor_archive_path).unwrap();
+            apply_patch_to_grf(
+                patching_method,
+                false,
+                DEFAULT_GRF_VERSION,
+                false,
+                case_sensitive_paths,
+                grf_archive_path,
+                &mut thor_archive,
+            )
+            .unwrap();
+            GrfArchive::open(grf_archive_path).unwrap()
+        };
+
+        // Files are replaced/removed regardless of their path's case
+        let check_case_insensitive_patching = |patching_method, grf_archive_path: &Path| {
+            let mut grf_archive = patch_grf(patching_method, false, grf_archive_path);
+            assert_eq!(1, grf_archive.file_count());
+            assert_eq!(
+                grf_archive
+                    .read_file_content("data\\texture\\a.bmp")
+                    .unwrap(),
+                b"new"
+            );
+        };
+        check_case_insensitive_patching(
+            GrfPatchingMethod::InPlace,
+            &temp_dir.path().join("ip.grf"),
+        );
+        check_case_insensitive_patching(
+            GrfPatchingMethod::OutOfPlace,
+            &temp_dir.path().join("oop.grf"),
+        );
+        // Paths that only differ by their case are different files otherwise
+        let grf_archive_path = temp_dir.path().join("strict.grf");
+        let mut grf_archive = patch_grf(GrfPatchingMethod::OutOfPlace, true, &grf_archive_path);
+        assert_eq!(3, grf_archive.file_count());
+        assert_eq!(
+            grf_archive
+                .read_file_content("data\\Texture\\a.bmp")
+                .unwrap(),
+            b"old"
+        );
+    }
+
+    #[test]
+    fn test_apply_patch_to_grf_ip_skip_unchanged() {
+        let grf_dir_path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources/tests/grf");
+        let thor_dir_path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources/tests/thor");
+        let temp_dir = tempdir().unwrap();
+        let grf_archive_path = temp_dir.path().join("empty.grf");
+        fs::copy(grf_dir_path.join("200-empty.grf"), &grf_archive_path).unwrap();
+        let mut thor_archive = ThorArchive::open(&thor_dir_path.join("small.thor")).unwrap();
+        let nb_of_patched_files = thor_archive.file_count() - 1;
+        let count_unchanged_files = |thor_archive: &mut ThorArchive<fs::File>| {
+            let mut grf_archive = GrfArchive::open(&grf_archive_path).unwrap();
+            unchanged_entries(thor_archive, |entry| {
+                grf_archive.read_file_content(&entry.relative_path).ok()
+            })
+            .unwrap()
+            .len()
+        };
+        let apply_patch = |thor_archive: &mut ThorArchive<fs::File>| {
+            apply_patch_to_grf(
+                GrfPatchingMethod::InPlace,
+                false,
+                DEFAULT_GRF_VERSION,
+                true,
+                false,
+                &grf_archive_path,
+                thor_archive,
+            )
+            .unwrap();
+        };
+        let relative_paths: Vec<String> = thor_archive
+            .get_entries()
+            .filter(|e| !e.is_internal())
+            .map(|e| e.relative_path.clone())
+            .collect();
+
+        assert_eq!(count_unchanged_files(&mut thor_archive), 0);
+        apply_patch(&mut thor_archive);
+        assert_eq!(
+            count_unchanged_files(&mut thor_archive),
+            nb_of_patched_files
+        );
+        // Files are already up to date and are skipped
+        apply_patch(&mut thor_archive);
+        assert!(grf_matches_patch(&grf_archive_path, &mut thor_archive, &relative_paths).unwrap());
+    }
+
+    #[test]
+    fn test_apply_patch_to_disk_skip_unchanged() {
+        let thor_dir_path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources/tests/thor");
+        let temp_dir = tempdir().unwrap();
+        let mut thor_archive = ThorArchive::open(&thor_dir_path.join("small.thor")).unwrap();
+        let relative_paths: Vec<String> = thor_archive
+            .get_entries()
+            .filter(|e| !e.is_internal())
+            .map(|e| e.relative_path.clone())
+            .collect();
+        apply_patch_to_disk(temp_dir.path(), true, &mut thor_archive).unwrap();
+        // Modified files are written again
+        let modified_file_path = temp_dir
+            .path()
+            .join("data/wav/se_subterranean_rustyengine.wav");
+        fs::write(&modified_file_path, b"modified").unwrap();
+        assert!(!disk_matches_patch(temp_dir.path(), &mut thor_archive, &relative_paths).unwrap());
+        apply_patch_to_disk(temp_dir.path(), true, &mut thor_archive).unwrap();
+        assert!(disk_matches_patch(temp_dir.path(), &mut thor_archive, &relative_paths).unwrap());
+    }
+
+    #[test]
+    fn test_grf_matches_patch() {
+        let grf_dir_path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources/tests/grf");
+        let thor_dir_path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources/tests/thor");
+        let temp_dir = tempdir().unwrap();
+        let thor_archive_path = thor_dir_path.join("small.thor");
+        let grf_archive_path = temp_dir.path().join("empty.grf");
+        fs::copy(grf_dir_path.join("200-empty.grf"), &grf_archive_path).unwrap();
+
+        let mut thor_archive = ThorArchive::open(&thor_archive_path).unwrap();
+        let relative_paths: Vec<String> = thor_archive
+            .get_entries()
+            .filter(|e| !e.is_internal())
+            .map(|e| e.relative_path.clone())
+            .collect();
+        assert!(!grf_matches_patch(&grf_archive_path, &mut thor_archive, &relative_paths).unwrap());
+        apply_patch_to_grf(
+            GrfPatchingMethod::InPlace,
+            false,
+            DEFAULT_GRF_VERSION,
+            false,
+            false,
+            &grf_archive_path,
+            &mut thor_archive,
+        )
+        .unwrap();
+        assert!(grf_matches_patch(&grf_archive_path, &mut thor_archive, &relative_paths).unwrap());
+    }
+
+    #[test]
+    fn test_disk_matches_patch() {
+        let thor_dir_path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("resources/tests/thor");
+        let temp_dir = tempdir().unwrap();
+        let mut thor_archive = ThorArchive::open(&thor_dir_path.join("small.thor")).unwrap();
+        let relative_paths: Vec<String> = thor_archive
+            .get_entries()
+            .filter(|e| !e.is_internal())
+            .map(|e| e.relative_path.clone())
+            .collect();
+        assert!(!disk_matches_patch(temp_dir.path(), &mut thor_archive, &relative_paths).unwrap());
+        apply_patch_to_disk(temp_dir.path(), false, &mut thor_archive).unwrap();
+        assert!(disk_matches_patch(temp_dir.path(), &mut thor_archive, &relative_paths).unwrap());
+    }
+
     fn patch_maintained_integrity(
         thor_file_path: &PathBuf,
         grf_file_path: &PathBuf,
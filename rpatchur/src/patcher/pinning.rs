@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::signature::decode_hex;
+use anyhow::{anyhow, Result};
+use rustls::{
+    Certificate, ClientConfig, RootCertStore, ServerCertVerified, ServerCertVerifier, TLSError,
+    WebPKIVerifier,
+};
+use sha2::{Digest, Sha256};
+
+/// Message of the TLS error produced when a certificate doesn't match its pin.
+const PIN_MISMATCH_MESSAGE: &str = "certificate doesn't match the pinned fingerprint";
+
+/// SHA-256 fingerprints of the certificates expected for each host.
+///
+/// Hosts without pins are verified the usual way only.
+#[derive(Default)]
+pub struct CertificatePins {
+    pins: HashMap<String, Vec<Vec<u8>>>,
+}
+
+impl CertificatePins {
+    /// Pins the given hex-encoded fingerprints (e.g., 'AB:CD:...') for `host`.
+    pub fn add(&mut self, host: &str, fingerprints: &[String]) -> Result<()> {
+        for fingerprint in fingerprints {
+            let fingerprint_bytes = decode_hex(&fingerprint.trim().replace(':', ""))
+                .filter(|bytes| bytes.len() == 32)
+                .ok_or_else(|| anyhow!("Invalid certificate fingerprint: '{}'", fingerprint))?;
+            self.pins
+                .entry(host.to_lowercase())
+                .or_default()
+                .push(fingerprint_bytes);
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pins.is_empty()
+    }
+
+    /// Checks that `certificate` (DER-encoded) matches one of the pins of
+    /// `host`, if any.
+    fn matches(&self, host: &str, certificate: &[u8]) -> bool {
+        match self.pins.get(&host.to_lowercase()) {
+            Some(fingerprints) => {
+                let fingerprint = Sha256::digest(certificate);
+                fingerprints.iter().any(|f| f[..] == fingerprint[..])
+            }
+            None => true,
+        }
+    }
+}
+
+/// Verifies certificates the usual way and then checks them against the
+/// configured pins.
+struct PinningVerifier {
+    webpki_verifier: WebPKIVerifier,
+    pins: CertificatePins,
+}
+
+impl ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(
+        &self,
+        roots: &RootCertStore,
+        presented_certs: &[Certificate],
+        dns_name: webpki::DNSNameRef,
+        ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        let verified = self.webpki_verifier.verify_server_cert(
+            roots,
+            presented_certs,
+            dns_name,
+            ocsp_response,
+        )?;
+        let host: &str = dns_name.into();
+        let certificate = presented_certs
+            .first()
+            .ok_or(TLSError::NoCertificatesPresented)?;
+        if self.pins.matches(host, &certificate.0) {
+            Ok(verified)
+        } else {
+            log::error!("Certificate pin mismatch for '{}'", host);
+            Err(TLSError::General(PIN_MISMATCH_MESSAGE.to_string()))
+        }
+    }
+}
+
+/// Builds a TLS configuration that rejects certificates that don't match
+/// `pins`.
+pub fn pinned_tls_config(pins: CertificatePins) -> ClientConfig {
+    let mut tls_config = ClientConfig::new();
+    // Note: Trust the system's root certificates, like the default TLS backend
+    tls_config.root_store = match rustls_native_certs::load_native_certs() {
+        Ok(root_store) => root_store,
+        Err((root_store, e)) => {
+            log::warn!("Failed to load some root certificates: {}", e);
+            root_store.unwrap_or_else(RootCertStore::empty)
+        }
+    };
+    // Note: Use HTTP/2 when the server supports it
+    tls_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    tls_config
+        .dangerous()
+        .set_certificate_verifier(Arc::new(PinningVerifier {
+            webpki_verifier: WebPKIVerifier::new(),
+            pins,
+        }));
+    tls_config
+}
+
+/// Returns `true` if `err` was caused by a certificate pin mismatch.
+pub fn is_pin_mismatch(err: &anyhow::Error) -> bool {
+    // Note: The TLS error is only reachable through the messages of the
+    // errors it's wrapped in
+    err.chain()
+        .any(|e| e.to_string().contains(PIN_MISMATCH_MESSAGE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_certificate_pins() {
+        let certificate = b"DER-encoded certificate";
+        let fingerprint: String = Sha256::digest(certificate)
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect::<Vec<String>>()
+            .join(":");
+        let mut pins = CertificatePins::default();
+        assert!(pins.is_empty());
+        pins.add("Patch.MyServer.com", &[fingerprint]).unwrap();
+        assert!(!pins.is_empty());
+
+        assert!(pins.matches("patch.myserver.com", certificate));
+        assert!(!pins.matches("patch.myserver.com", b"Another certificate"));
+        // Hosts without pins aren't restricted
+        assert!(pins.matches("mirror.myserver.com", b"Another certificate"));
+
+        assert!(pins
+            .add("patch.myserver.com", &["abcd".to_string()])
+            .is_err());
+        assert!(pins
+            .add("patch.myserver.com", &["not a fingerprint".to_string()])
+            .is_err());
+    }
+
+    #[test]
+    fn test_is_pin_mismatch() {
+        let err =
+            anyhow!("unexpected error: {}", PIN_MISMATCH_MESSAGE).context("Failed to GET URL");
+        assert!(is_pin_mismatch(&err));
+        assert!(!is_pin_mismatch(&anyhow!("Connection refused")));
+    }
+}
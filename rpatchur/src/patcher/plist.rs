@@ -0,0 +1,94 @@
+use super::config::PatchListFormat;
+use anyhow::{Context, Result};
+use gruf::thor::{self, ThorPatchInfo, ThorPatchList};
+use serde::Deserialize;
+
+/// Entry of a patch list in the JSON format.
+#[derive(Deserialize)]
+struct JsonPatchInfo {
+    index: usize, // Index of the patch
+    file: String, // File name of the patch
+}
+
+/// Parses the content of a patch list file.
+///
+/// Entries that cannot be parsed are skipped with a warning that indicates
+/// their line number (or element number for JSON patch lists).
+///
+/// Returns the patch list, sorted by patch index.
+pub fn parse_patch_list(content: &str, format: PatchListFormat) -> Result<ThorPatchList> {
+    match format {
+        PatchListFormat::Text => {
+            let (patch_list, invalid_lines) = thor::patch_list_from_string_checked(content);
+            for line_number in invalid_lines {
+                log::warn!("Ignoring invalid patch list entry at line {}", line_number);
+            }
+            Ok(patch_list)
+        }
+        PatchListFormat::Json => parse_json_patch_list(content),
+    }
+}
+
+fn parse_json_patch_list(content: &str) -> Result<ThorPatchList> {
+    let elements: Vec<serde_json::Value> =
+        serde_json::from_str(content).context("Patch list is not a JSON array")?;
+    let mut patch_list: ThorPatchList = Vec::with_capacity(elements.len());
+    for (element_index, element) in elements.into_iter().enumerate() {
+        match serde_json::from_value::<JsonPatchInfo>(element) {
+            Ok(patch_info) if !patch_info.file.trim().is_empty() => {
+                patch_list.push(ThorPatchInfo {
+                    index: patch_info.index,
+                    file_name: patch_info.file,
+                })
+            }
+            Ok(_) => log::warn!(
+                "Ignoring invalid patch list entry at element {}: empty file name",
+                element_index
+            ),
+            Err(e) => log::warn!(
+                "Ignoring invalid patch list entry at element {}: {}",
+                element_index,
+                e
+            ),
+        }
+    }
+    // Sort patch list by index
+    patch_list.sort_by_key(|patch_info| patch_info.index);
+    Ok(patch_list)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_text_patch_list() {
+        let content = "// Comment\r\n\r\n2 b.thor\r\ninvalid\r\n1 a.thor\r\n";
+        let patch_list = parse_patch_list(content, PatchListFormat::Text).unwrap();
+        assert_eq!(patch_list.len(), 2);
+        assert_eq!(patch_list[0].index, 1);
+        assert_eq!(patch_list[0].file_name, "a.thor");
+        assert_eq!(patch_list[1].index, 2);
+        assert_eq!(patch_list[1].file_name, "b.thor");
+    }
+
+    #[test]
+    fn test_parse_json_patch_list() {
+        let content = r#"[
+            {"index": 3, "file": "c.thor"},
+            {"index": 1, "file": "a.rgz"},
+            {"index": "2", "file": "b.thor"},
+            {"index": 4, "file": ""},
+            {"file": "d.thor"}
+        ]"#;
+        let patch_list = parse_patch_list(content, PatchListFormat::Json).unwrap();
+        assert_eq!(patch_list.len(), 2);
+        assert_eq!(patch_list[0].index, 1);
+        assert_eq!(patch_list[0].file_name, "a.rgz");
+        assert_eq!(patch_list[1].index, 3);
+        assert_eq!(patch_list[1].file_name, "c.thor");
+
+        assert!(parse_patch_list("{}", PatchListFormat::Json).is_err());
+        assert!(parse_patch_list("1 a.thor", PatchListFormat::Json).is_err());
+    }
+}
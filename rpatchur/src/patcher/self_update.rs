@@ -0,0 +1,278 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::config::SelfUpdateConfiguration;
+use super::signature::{fetch_signature, SignatureVerifier};
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use url::Url;
+
+const PKG_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Description of the latest version of the patcher, as published on the
+/// remote server.
+#[derive(Deserialize)]
+struct UpdateManifest {
+    version: String, // Version of the published patcher
+    url: String,     // URL of the published executable
+    sha256: String,  // SHA-256 checksum of the published executable
+}
+
+/// Removes the executable left behind by a previous update, if any.
+pub fn cleanup_previous_update(exe_path: &Path) {
+    let old_exe_path = get_old_exe_path(exe_path);
+    if old_exe_path.exists() {
+        if let Err(e) = fs::remove_file(&old_exe_path) {
+            log::warn!("Failed to remove previous executable: {}", e);
+        }
+    }
+}
+
+/// Checks for a newer version of the patcher and replaces the executable
+/// located at `exe_path` with it.
+///
+/// The update manifest must be signed with the key of `verifier`, as it
+/// contains the checksum of the new executable.
+///
+/// The running executable is renamed before being replaced (which is allowed
+/// on Windows, unlike overwriting it), the new version is used on the next
+/// launch.
+///
+/// Returns `true` if the executable has been updated.
+pub async fn update_executable(
+    client: &reqwest::Client,
+    config: &SelfUpdateConfiguration,
+    verifier: &SignatureVerifier,
+    exe_path: &Path,
+) -> Result<bool> {
+    let manifest_url = Url::parse(&config.url).context("Failed to parse update manifest URL")?;
+    let resp = client
+        .get(manifest_url.clone())
+        .send()
+        .await
+        .context("Failed to GET URL")?;
+    if !resp.status().is_success() {
+        return Err(anyhow!("Update manifest not found on the remote server"));
+    }
+    let manifest_content = resp.text().await.context("Invalid response body")?;
+    let signature = fetch_signature(client, &manifest_url)
+        .await
+        .context("Failed to retrieve the signature of the update manifest")?;
+    verifier
+        .verify(manifest_content.as_bytes(), &signature)
+        .context("Signature of the update manifest is invalid")?;
+    let manifest: UpdateManifest =
+        serde_json::from_str(&manifest_content).context("Invalid update manifest")?;
+    if !is_newer_version(&manifest.version, PKG_VERSION) {
+        log::info!("Patcher is up to date ({})", PKG_VERSION);
+        return Ok(false);
+    }
+
+    log::info!("Downloading patcher version {}", manifest.version);
+    let resp = client
+        .get(manifest.url.as_str())
+        .send()
+        .await
+        .context("Failed to download the new executable")?;
+    if !resp.status().is_success() {
+        return Err(anyhow!("New executable not found on the remote server"));
+    }
+    let exe_content = resp
+        .bytes()
+        .await
+        .context("Failed to download the new executable")?;
+    let checksum = format!("{:x}", Sha256::digest(&exe_content));
+    if !checksum.eq_ignore_ascii_case(manifest.sha256.trim()) {
+        return Err(anyhow!(
+            "New executable is corrupt (expected checksum {}, got {})",
+            manifest.sha256,
+            checksum
+        ));
+    }
+
+    // Write the new executable next to the current one
+    let new_exe_path = get_new_exe_path(exe_path);
+    fs::write(&new_exe_path, &exe_content).context("Failed to write the new executable")?;
+    fs::set_permissions(&new_exe_path, fs::metadata(exe_path)?.permissions())
+        .context("Failed to set the new executable's permissions")?;
+    // Swap executables
+    let old_exe_path = get_old_exe_path(exe_path);
+    let _ = fs::remove_file(&old_exe_path);
+    fs::rename(exe_path, &old_exe_path).context("Failed to rename the current executable")?;
+    if let Err(e) = fs::rename(&new_exe_path, exe_path) {
+        // Put the current executable back in place
+        let _ = fs::rename(&old_exe_path, exe_path);
+        return Err(anyhow!("Failed to replace the current executable: {}", e));
+    }
+    log::info!("Patcher updated to version {}", manifest.version);
+    Ok(true)
+}
+
+/// Indicates whether `version` is more recent than `current_version`.
+///
+/// Versions are compared as dot-separated numbers. Versions that cannot be
+/// parsed are never considered more recent.
+fn is_newer_version(version: &str, current_version: &str) -> bool {
+    let parse_version = |version: &str| {
+        version
+            .trim_start_matches('v')
+            .split('.')
+            .map(|number| number.parse::<u64>())
+            .collect::<std::result::Result<Vec<u64>, _>>()
+    };
+    match (parse_version(version), parse_version(current_version)) {
+        (Ok(version), Ok(current_version)) => version > current_version,
+        _ => false,
+    }
+}
+
+fn get_new_exe_path(exe_path: &Path) -> PathBuf {
+    exe_path.with_extension("new")
+}
+
+fn get_old_exe_path(exe_path: &Path) -> PathBuf {
+    exe_path.with_extension("old")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signer};
+    use httptest::{matchers::*, responders::*, Expectation, Server};
+    use tempfile::tempdir;
+
+    fn encode_hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn test_keypair() -> Keypair {
+        let secret = SecretKey::from_bytes(&[7; 32]).unwrap();
+        let public = PublicKey::from(&secret);
+        Keypair { secret, public }
+    }
+
+    fn test_verifier() -> SignatureVerifier {
+        SignatureVerifier::from_hex(&encode_hex(test_keypair().public.as_bytes())).unwrap()
+    }
+
+    /// Serves an update manifest and its signature.
+    fn serve_manifest(server: &Server, manifest: &serde_json::Value, signed_content: &[u8]) {
+        let signature = test_keypair().sign(&Sha256::digest(signed_content));
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/update.json"))
+                .respond_with(status_code(200).body(manifest.to_string())),
+        );
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/update.json.sig"))
+                .respond_with(status_code(200).body(encode_hex(&signature.to_bytes()))),
+        );
+    }
+
+    #[test]
+    fn test_is_newer_version() {
+        assert!(is_newer_version("0.2.1", "0.2.0"));
+        assert!(is_newer_version("v0.10.0", "0.9.3"));
+        assert!(!is_newer_version("0.2.0", "0.2.0"));
+        assert!(!is_newer_version("0.1.9", "0.2.0"));
+        assert!(!is_newer_version("beta", "0.2.0"));
+        assert!(!is_newer_version("0.3.0", "beta"));
+    }
+
+    #[tokio::test]
+    async fn test_update_executable() {
+        let exe_content = b"new executable".to_vec();
+        let server = Server::run();
+        let manifest = serde_json::json!({
+            "version": "99.0.0",
+            "url": server.url("/rpatchur.exe").to_string(),
+            "sha256": format!("{:x}", Sha256::digest(&exe_content)),
+        });
+        serve_manifest(&server, &manifest, manifest.to_string().as_bytes());
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/rpatchur.exe"))
+                .respond_with(status_code(200).body(exe_content.clone())),
+        );
+
+        let temp_dir = tempdir().unwrap();
+        let exe_path = temp_dir.path().join("rpatchur.exe");
+        fs::write(&exe_path, b"old executable").unwrap();
+        let config = SelfUpdateConfiguration {
+            enabled: true,
+            url: server.url("/update.json").to_string(),
+        };
+        assert!(update_executable(
+            &reqwest::Client::new(),
+            &config,
+            &test_verifier(),
+            &exe_path
+        )
+        .await
+        .unwrap());
+        assert_eq!(exe_content, fs::read(&exe_path).unwrap());
+        assert!(get_old_exe_path(&exe_path).exists());
+        cleanup_previous_update(&exe_path);
+        assert!(!get_old_exe_path(&exe_path).exists());
+    }
+
+    #[tokio::test]
+    async fn test_update_executable_corrupt() {
+        let server = Server::run();
+        let manifest = serde_json::json!({
+            "version": "99.0.0",
+            "url": server.url("/rpatchur.exe").to_string(),
+            "sha256": "0000",
+        });
+        serve_manifest(&server, &manifest, manifest.to_string().as_bytes());
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/rpatchur.exe"))
+                .respond_with(status_code(200).body("new executable")),
+        );
+
+        let temp_dir = tempdir().unwrap();
+        let exe_path = temp_dir.path().join("rpatchur.exe");
+        fs::write(&exe_path, b"old executable").unwrap();
+        let config = SelfUpdateConfiguration {
+            enabled: true,
+            url: server.url("/update.json").to_string(),
+        };
+        assert!(update_executable(
+            &reqwest::Client::new(),
+            &config,
+            &test_verifier(),
+            &exe_path
+        )
+        .await
+        .is_err());
+        assert_eq!(b"old executable".to_vec(), fs::read(&exe_path).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_update_executable_unsigned() {
+        let server = Server::run();
+        let manifest = serde_json::json!({
+            "version": "99.0.0",
+            "url": server.url("/rpatchur.exe").to_string(),
+            "sha256": format!("{:x}", Sha256::digest(b"new executable")),
+        });
+        // The signature doesn't match the manifest
+        serve_manifest(&server, &manifest, b"another manifest");
+
+        let temp_dir = tempdir().unwrap();
+        let exe_path = temp_dir.path().join("rpatchur.exe");
+        fs::write(&exe_path, b"old executable").unwrap();
+        let config = SelfUpdateConfiguration {
+            enabled: true,
+            url: server.url("/update.json").to_string(),
+        };
+        assert!(update_executable(
+            &reqwest::Client::new(),
+            &config,
+            &test_verifier(),
+            &exe_path
+        )
+        .await
+        .is_err());
+        assert_eq!(b"old executable".to_vec(), fs::read(&exe_path).unwrap());
+    }
+}
@@ -0,0 +1,156 @@
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use super::error::ChecksumError;
+use anyhow::{anyhow, Context, Result};
+use ed25519_dalek::{PublicKey, Signature, Verifier};
+use reqwest::StatusCode;
+use sha2::{Digest, Sha256};
+use url::Url;
+
+/// Extension of the files containing the signatures of published files.
+const SIGNATURE_EXTENSION: &str = ".sig";
+
+/// Verifies the Ed25519 signatures of the files published on the patch host.
+///
+/// Each published file comes with a signature file (e.g., 'plist.txt.sig'),
+/// which contains the hex-encoded signature of the SHA-256 checksum of the
+/// published file.
+pub struct SignatureVerifier {
+    public_key: PublicKey,
+}
+
+impl SignatureVerifier {
+    /// Creates a verifier from a hex-encoded Ed25519 public key.
+    pub fn from_hex(public_key: &str) -> Result<Self> {
+        let public_key_bytes =
+            decode_hex(public_key.trim()).ok_or_else(|| anyhow!("Invalid public key encoding"))?;
+        let public_key = PublicKey::from_bytes(&public_key_bytes)
+            .map_err(|e| anyhow!("Invalid public key: {}", e))?;
+        Ok(Self { public_key })
+    }
+
+    /// Checks that `signature` is a valid signature of `data`'s checksum.
+    pub fn verify(&self, data: &[u8], signature: &str) -> Result<()> {
+        self.verify_digest(&Sha256::digest(data), signature)
+    }
+
+    /// Checks that `signature` is a valid signature of the checksum of the file
+    /// located at `file_path`.
+    pub fn verify_file<P: AsRef<Path>>(&self, file_path: P, signature: &str) -> Result<()> {
+        let mut file = File::open(file_path).context("Failed to open file")?;
+        let mut hasher = Sha256::new();
+        io::copy(&mut file, &mut hasher).context("Failed to read file")?;
+        self.verify_digest(&hasher.finalize(), signature)
+    }
+
+    fn verify_digest(&self, digest: &[u8], signature: &str) -> Result<()> {
+        let signature_bytes =
+            decode_hex(signature.trim()).ok_or_else(|| anyhow!("Invalid signature encoding"))?;
+        let signature =
+            Signature::try_from(&signature_bytes[..]).map_err(|_| anyhow!("Invalid signature"))?;
+        self.public_key
+            .verify(digest, &signature)
+            .map_err(|_| ChecksumError("Signature mismatch".to_string()).into())
+    }
+}
+
+/// Downloads the signature of the file located at `file_url`.
+pub async fn fetch_signature(client: &reqwest::Client, file_url: &Url) -> Result<String> {
+    let signature_url = signature_url(file_url)?;
+    let resp = client
+        .get(signature_url)
+        .send()
+        .await
+        .context("Failed to GET URL")?;
+    if resp.status() != StatusCode::OK {
+        return Err(anyhow!("Signature file not found on the remote server"));
+    }
+    resp.text().await.context("Invalid response body")
+}
+
+/// Returns the URL of the signature of the file located at `file_url`.
+fn signature_url(file_url: &Url) -> Result<Url> {
+    let mut signature_url = file_url.clone();
+    let path = format!("{}{}", file_url.path(), SIGNATURE_EXTENSION);
+    signature_url.set_path(&path);
+    if signature_url.path().ends_with(SIGNATURE_EXTENSION) {
+        Ok(signature_url)
+    } else {
+        Err(anyhow!("Invalid URL: '{}'", file_url))
+    }
+}
+
+pub(super) fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    s.as_bytes()
+        .chunks(2)
+        .map(|digits| match digits.len() {
+            2 => std::str::from_utf8(digits)
+                .ok()
+                .and_then(|digits| u8::from_str_radix(digits, 16).ok()),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Keypair, SecretKey, Signer};
+
+    fn encode_hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn test_keypair() -> Keypair {
+        let secret = SecretKey::from_bytes(&[7; 32]).unwrap();
+        let public = PublicKey::from(&secret);
+        Keypair { secret, public }
+    }
+
+    #[test]
+    fn test_verify() {
+        let keypair = test_keypair();
+        let verifier = SignatureVerifier::from_hex(&encode_hex(keypair.public.as_bytes())).unwrap();
+        let data = b"1 patch1.thor\n2 patch2.thor\n";
+        let signature = encode_hex(&keypair.sign(&Sha256::digest(data)).to_bytes());
+
+        assert!(verifier.verify(data, &signature).is_ok());
+        assert!(verifier.verify(b"1 malicious.thor\n", &signature).is_err());
+        assert!(verifier.verify(data, &signature[2..]).is_err());
+        assert!(verifier.verify(data, "not a signature").is_err());
+    }
+
+    #[test]
+    fn test_verify_file() {
+        let keypair = test_keypair();
+        let verifier = SignatureVerifier::from_hex(&encode_hex(keypair.public.as_bytes())).unwrap();
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("patch.thor");
+        std::fs::write(&file_path, b"THOR content").unwrap();
+        let signature = encode_hex(&keypair.sign(&Sha256::digest(b"THOR content")).to_bytes());
+
+        assert!(verifier.verify_file(&file_path, &signature).is_ok());
+        std::fs::write(&file_path, b"Tampered content").unwrap();
+        assert!(verifier.verify_file(&file_path, &signature).is_err());
+    }
+
+    #[test]
+    fn test_from_hex() {
+        assert!(SignatureVerifier::from_hex("").is_err());
+        assert!(SignatureVerifier::from_hex("zz").is_err());
+        assert!(SignatureVerifier::from_hex(&"a".repeat(63)).is_err());
+        assert!(SignatureVerifier::from_hex(&"ab".repeat(31)).is_err());
+    }
+
+    #[test]
+    fn test_signature_url() {
+        let url = Url::parse("https://example.com/patches/plist.txt?v=1").unwrap();
+        assert_eq!(
+            signature_url(&url).unwrap().as_str(),
+            "https://example.com/patches/plist.txt.sig?v=1"
+        );
+    }
+}
@@ -0,0 +1,190 @@
+use anyhow::{anyhow, Context, Result};
+use reqwest::StatusCode;
+use tokio::sync::Mutex;
+use url::{form_urlencoded, Url};
+
+const FILE_PLACEHOLDER: &str = "{file}";
+const TOKEN_PLACEHOLDER: &str = "{token}";
+
+/// Builds the URLs of patches from a template (e.g.,
+/// 'https://host/{file}?token={token}'), for hosts that only serve files
+/// through signed or token-authenticated URLs.
+///
+/// The token is retrieved from an authentication endpoint when the first URL
+/// is built. The endpoint returns either the token itself or a JSON object
+/// with a `token` field.
+#[derive(Debug)]
+pub struct SignedUrlBuilder {
+    template: String,
+    token_url: Option<Url>,
+    token: Mutex<Option<String>>,
+}
+
+impl SignedUrlBuilder {
+    /// Creates a builder for `template`, with tokens retrieved from
+    /// `token_url`.
+    pub fn new(template: &str, token_url: Option<&str>) -> Result<Self> {
+        let token_url = match token_url {
+            Some(token_url) => Some(
+                Url::parse(token_url)
+                    .context(format!("Failed to parse 'token_url': '{}'", token_url))?,
+            ),
+            None => None,
+        };
+        if token_url.is_none() && template.contains(TOKEN_PLACEHOLDER) {
+            return Err(anyhow!("'patch_url_template' requires a 'token_url'"));
+        }
+        Ok(Self {
+            template: template.to_string(),
+            token_url,
+            token: Mutex::new(None),
+        })
+    }
+
+    /// Returns the URL of `file_name`, along with the token it contains.
+    pub async fn url(&self, client: &reqwest::Client, file_name: &str) -> Result<(Url, String)> {
+        let token = {
+            // Note: The lock is held so that concurrent calls share a token
+            let mut token = self.token.lock().await;
+            match &*token {
+                Some(v) => v.clone(),
+                None => {
+                    let new_token = match &self.token_url {
+                        Some(token_url) => fetch_token(client, token_url).await?,
+                        None => String::new(),
+                    };
+                    *token = Some(new_token.clone());
+                    new_token
+                }
+            }
+        };
+        let url = expand_template(&self.template, file_name, &token)?;
+        Ok((url, token))
+    }
+
+    /// Retrieves a new token, unless `expired_token` has already been
+    /// replaced (e.g., by a concurrent download).
+    pub async fn refresh_token(&self, client: &reqwest::Client, expired_token: &str) -> Result<()> {
+        let token_url = match &self.token_url {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+        let mut token = self.token.lock().await;
+        if token.as_deref() == Some(expired_token) {
+            log::info!("Refreshing the patch host's token");
+            *token = Some(fetch_token(client, token_url).await?);
+        }
+        Ok(())
+    }
+}
+
+/// Replaces the placeholders of `template`.
+fn expand_template(template: &str, file_name: &str, token: &str) -> Result<Url> {
+    // Note: Tokens are encoded so that they can be used in query strings
+    let encoded_token: String = form_urlencoded::byte_serialize(token.as_bytes()).collect();
+    let url = template
+        .replace(FILE_PLACEHOLDER, file_name)
+        .replace(TOKEN_PLACEHOLDER, &encoded_token);
+    Url::parse(&url).context(format!(
+        "Invalid file name '{}' given in patch list file",
+        file_name
+    ))
+}
+
+async fn fetch_token(client: &reqwest::Client, token_url: &Url) -> Result<String> {
+    let resp = client
+        .get(token_url.clone())
+        .send()
+        .await
+        .context("Failed to GET URL")
+        .context("Failed to retrieve a token for the patch host")?;
+    if resp.status() != StatusCode::OK {
+        return Err(anyhow!(
+            "Failed to retrieve a token for the patch host ({})",
+            resp.status()
+        ));
+    }
+    let body = resp.text().await.context("Invalid response body")?;
+    parse_token(&body)
+}
+
+/// Extracts the token from the body of the authentication endpoint's
+/// response.
+fn parse_token(body: &str) -> Result<String> {
+    let token = match serde_json::from_str::<serde_json::Value>(body) {
+        Ok(serde_json::Value::Object(object)) => object
+            .get("token")
+            .and_then(|token| token.as_str())
+            .ok_or_else(|| anyhow!("Token missing from the authentication response"))?
+            .to_string(),
+        _ => body.trim().to_string(),
+    };
+    if token.is_empty() {
+        return Err(anyhow!("Token missing from the authentication response"));
+    }
+    Ok(token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use httptest::{matchers::*, responders::*, Expectation, Server};
+
+    #[test]
+    fn test_expand_template() {
+        assert_eq!(
+            expand_template(
+                "https://cdn.myserver.com/{file}?token={token}",
+                "1.thor",
+                "a+b/c"
+            )
+            .unwrap()
+            .as_str(),
+            "https://cdn.myserver.com/1.thor?token=a%2Bb%2Fc"
+        );
+        assert!(expand_template("{file}", "1.thor", "").is_err());
+    }
+
+    #[test]
+    fn test_parse_token() {
+        assert_eq!(parse_token("abc123\r\n").unwrap(), "abc123");
+        assert_eq!(
+            parse_token(r#"{"token": "abc123", "expires_in": 3600}"#).unwrap(),
+            "abc123"
+        );
+        assert!(parse_token(r#"{"expires_in": 3600}"#).is_err());
+        assert!(parse_token("  ").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_refresh_token() {
+        let server = Server::run();
+        server.expect(
+            Expectation::matching(request::method_path("GET", "/token"))
+                .times(2)
+                .respond_with(cycle![
+                    status_code(200).body("token1"),
+                    status_code(200).body("token2"),
+                ]),
+        );
+        let client = reqwest::Client::new();
+        let token_url = server.url("/token").to_string();
+        let builder = SignedUrlBuilder::new(
+            "https://cdn.myserver.com/{file}?token={token}",
+            Some(&token_url),
+        )
+        .unwrap();
+        let (url, token) = builder.url(&client, "1.thor").await.unwrap();
+        // The token is only retrieved once
+        builder.url(&client, "2.thor").await.unwrap();
+        assert_eq!(url.as_str(), "https://cdn.myserver.com/1.thor?token=token1");
+
+        builder.refresh_token(&client, &token).await.unwrap();
+        // The token has already been refreshed
+        builder.refresh_token(&client, &token).await.unwrap();
+        let (url, _) = builder.url(&client, "1.thor").await.unwrap();
+        assert_eq!(url.as_str(), "https://cdn.myserver.com/1.thor?token=token2");
+
+        assert!(SignedUrlBuilder::new("https://cdn.myserver.com/{file}?t={token}", None).is_err());
+    }
+}
@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context, Result};
+use gruf::thor;
+use reqwest::header;
+use serde::Serialize;
+
+/// File of the game client whose content doesn't match the checksum it has in
+/// the latest applied patch.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct TamperedFile {
+    pub relative_path: String,
+    pub expected_checksum: u32,
+    pub actual_checksum: Option<u32>, // `None` if the file is missing
+}
+
+/// Report sent to the configured URL when tampered files are found.
+#[derive(Serialize)]
+pub struct TamperReport<'a> {
+    pub patch_index: usize,   // Index of the patch used as reference
+    pub patch_name: &'a str,  // Name of the patch used as reference
+    pub client_name: &'a str, // Name of the GRF or relative directory that was checked
+    pub tampered_files: &'a [TamperedFile],
+}
+
+/// Compares the checksums of the given files to the checksums contained in a
+/// patch's integrity file.
+///
+/// Files without a checksum in `checksums` are ignored, as the patch doesn't
+/// say anything about them. `read_current_content` returns `None` for files
+/// that are missing from the game client.
+pub fn find_tampered_files<F>(
+    checksums: &HashMap<String, u32>,
+    relative_paths: &[String],
+    mut read_current_content: F,
+) -> Result<Vec<TamperedFile>>
+where
+    F: FnMut(&str) -> Result<Option<Vec<u8>>>,
+{
+    let mut tampered_files = vec![];
+    for relative_path in relative_paths {
+        let expected_checksum = match checksums.get(relative_path) {
+            Some(v) => *v,
+            None => {
+                log::debug!("No checksum available for '{}'", relative_path);
+                continue;
+            }
+        };
+        let actual_checksum = read_current_content(relative_path)
+            .context(format!("Failed to read '{}'", relative_path))?
+            .map(|content| thor::checksum(&content));
+        if actual_checksum != Some(expected_checksum) {
+            tampered_files.push(TamperedFile {
+                relative_path: relative_path.clone(),
+                expected_checksum,
+                actual_checksum,
+            });
+        }
+    }
+    Ok(tampered_files)
+}
+
+/// Sends `report` to `report_url`, as JSON.
+pub async fn send_tamper_report(
+    client: &reqwest::Client,
+    report_url: &str,
+    report: &TamperReport<'_>,
+) -> Result<()> {
+    let body = serde_json::to_vec(report).context("Failed to serialize tamper report")?;
+    let resp = client
+        .post(report_url)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(body)
+        .send()
+        .await
+        .context("Failed to POST URL")?;
+    if !resp.status().is_success() {
+        return Err(anyhow!(
+            "Tamper report rejected by the remote server ({})",
+            resp.status()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_tampered_files() {
+        let checksums: HashMap<String, u32> = vec![
+            ("data\\valid.txt".to_string(), thor::checksum(b"valid")),
+            (
+                "data\\tampered.txt".to_string(),
+                thor::checksum(b"original"),
+            ),
+            ("data\\missing.txt".to_string(), thor::checksum(b"missing")),
+        ]
+        .into_iter()
+        .collect();
+        let relative_paths: Vec<String> = vec![
+            "data\\valid.txt".to_string(),
+            "data\\tampered.txt".to_string(),
+            "data\\missing.txt".to_string(),
+            "data\\unknown.txt".to_string(),
+        ];
+        let tampered_files = find_tampered_files(&checksums, &relative_paths, |relative_path| {
+            Ok(match relative_path {
+                "data\\valid.txt" => Some(b"valid".to_vec()),
+                "data\\tampered.txt" => Some(b"modified".to_vec()),
+                "data\\unknown.txt" => Some(b"unknown".to_vec()),
+                _ => None,
+            })
+        })
+        .unwrap();
+        assert_eq!(
+            tampered_files,
+            vec![
+                TamperedFile {
+                    relative_path: "data\\tampered.txt".to_string(),
+                    expected_checksum: thor::checksum(b"original"),
+                    actual_checksum: Some(thor::checksum(b"modified")),
+                },
+                TamperedFile {
+                    relative_path: "data\\missing.txt".to_string(),
+                    expected_checksum: thor::checksum(b"missing"),
+                    actual_checksum: None,
+                },
+            ]
+        );
+
+        assert!(
+            find_tampered_files(&checksums, &relative_paths, |_| Err(anyhow!("I/O error")))
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,63 @@
+use std::time::{Duration, Instant};
+
+use tokio::sync::Mutex;
+use tokio::time;
+
+/// Bandwidth limiter that can be shared between concurrent downloads.
+///
+/// Each downloaded chunk reserves a time slot proportional to its size, and
+/// downloads wait for the end of their slot before reading more data.
+pub struct BandwidthThrottle {
+    bytes_per_sec: u64,
+    next_slot: Mutex<Instant>,
+}
+
+impl BandwidthThrottle {
+    /// Creates a throttle limited to `bytes_per_sec`.
+    ///
+    /// Returns `None` if `bytes_per_sec` is zero (i.e., unlimited).
+    pub fn new(bytes_per_sec: u64) -> Option<Self> {
+        if bytes_per_sec == 0 {
+            return None;
+        }
+        Some(Self {
+            bytes_per_sec,
+            next_slot: Mutex::new(Instant::now()),
+        })
+    }
+
+    /// Waits until `byte_count` bytes can be consumed without exceeding the
+    /// bandwidth limit.
+    pub async fn consume(&self, byte_count: usize) {
+        let slot_end = {
+            let mut next_slot = self.next_slot.lock().await;
+            // Don't accumulate unused bandwidth while idle
+            let slot_start = std::cmp::max(*next_slot, Instant::now());
+            let slot_duration =
+                Duration::from_secs_f64(byte_count as f64 / self.bytes_per_sec as f64);
+            *next_slot = slot_start + slot_duration;
+            *next_slot
+        };
+        time::delay_for(slot_end.saturating_duration_since(Instant::now())).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unlimited() {
+        assert!(BandwidthThrottle::new(0).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_consume() {
+        let throttle = BandwidthThrottle::new(1000).unwrap();
+        let start = Instant::now();
+        for _ in 0..3 {
+            throttle.consume(100).await;
+        }
+        assert!(start.elapsed() >= Duration::from_millis(300));
+    }
+}
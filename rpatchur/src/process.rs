@@ -12,10 +12,44 @@ where
     // Fold parameter list into a String
     let exe_parameter = exe_arguments
         .into_iter()
-        .fold(String::new(), |a: String, b| a + " \"" + b.as_ref() + "\"");
+        .map(|arg| quote_windows_argument(arg.as_ref()))
+        .collect::<Vec<String>>()
+        .join(" ");
     windows::spawn_elevated_win32_process(exe_path, &exe_parameter)
 }
 
+/// Quotes a command-line argument so that it's parsed back as a single
+/// argument by `CommandLineToArgvW` and the MSVC runtime.
+///
+/// Quotes and the backslashes preceding them are escaped, backslashes are
+/// kept as-is otherwise.
+#[cfg(any(windows, test))]
+fn quote_windows_argument(arg: &str) -> String {
+    let mut quoted = String::with_capacity(arg.len() + 2);
+    quoted.push('"');
+    let mut backslash_count = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => backslash_count += 1,
+            '"' => {
+                // Escape preceding backslashes and the quote itself
+                quoted.push_str(&"\\".repeat(2 * backslash_count + 1));
+                quoted.push(c);
+                backslash_count = 0;
+            }
+            _ => {
+                quoted.push_str(&"\\".repeat(backslash_count));
+                quoted.push(c);
+                backslash_count = 0;
+            }
+        }
+    }
+    // Escape trailing backslashes, since they precede the closing quote
+    quoted.push_str(&"\\".repeat(2 * backslash_count));
+    quoted.push('"');
+    quoted
+}
+
 /// Starts an executable file in a cross-platform way.
 ///
 /// This is the non-Windows version.
@@ -95,3 +129,19 @@ mod windows {
         Ok(result as usize > 32)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote_windows_argument() {
+        assert_eq!(r#""1sak1""#, quote_windows_argument("1sak1"));
+        assert_eq!(r#""""#, quote_windows_argument(""));
+        assert_eq!(r#""with spaces""#, quote_windows_argument("with spaces"));
+        assert_eq!(r#""a\"b""#, quote_windows_argument(r#"a"b"#));
+        assert_eq!(r#""C:\dir\\""#, quote_windows_argument(r#"C:\dir\"#));
+        assert_eq!(r#""a\\\"b""#, quote_windows_argument(r#"a\"b"#));
+        assert_eq!(r#""a\b""#, quote_windows_argument(r#"a\b"#));
+    }
+}
@@ -1,23 +1,53 @@
 use std::fs;
 use std::include_str;
-use std::path::PathBuf;
 
-use crate::patcher::{get_patcher_name, PatcherCommand, PatcherConfiguration};
+use crate::patcher::{
+    get_cache_file_path, get_history_file_path, read_history_file, PatcherCommand,
+    PatcherConfiguration, PatchingError,
+};
 use crate::process::start_executable;
 use futures::executor::block_on;
 use serde::Deserialize;
-use serde_json::Value;
+use serde_json::{json, Value};
 use tokio::sync::mpsc;
 use web_view::{Content, Handle, WebView};
 
+/// Function called with each status of the patching process.
+pub type PatchingStatusCallback = Box<dyn Fn(PatchingStatus) + Send + Sync>;
+
+/// Indicates what's updated with the status of the patching process.
+enum UIBackend {
+    WebView(Handle<WebViewUserData>),
+    Callback(PatchingStatusCallback),
+}
+
 /// 'Opaque" struct that can be used to update the UI.
 pub struct UIController {
-    web_view_handle: Handle<WebViewUserData>,
+    backend: UIBackend,
 }
 impl UIController {
     pub fn new<'a>(web_view: &WebView<'a, WebViewUserData>) -> UIController {
         UIController {
-            web_view_handle: web_view.handle(),
+            backend: UIBackend::WebView(web_view.handle()),
+        }
+    }
+
+    /// Creates a controller that prints the status of the patching process to
+    /// the standard output instead of updating a web view.
+    pub fn headless() -> UIController {
+        UIController::with_callback(print_patching_status)
+    }
+
+    /// Creates a controller that passes the status of the patching process to
+    /// `status_callback` instead of updating a web view.
+    ///
+    /// This allows driving any UI with the patching routines.
+    pub fn with_callback<F>(status_callback: F) -> UIController
+    where
+        F: Fn(PatchingStatus) + Send + Sync + 'static,
+    {
+        UIController {
+            backend: UIBackend::Callback(Box::new(status_callback)),
         }
     }
 
@@ -25,21 +55,88 @@ impl UIController {
     ///
     /// This updates the UI with useful information.
     pub async fn dispatch_patching_status(&self, status: PatchingStatus) {
-        if let Err(e) = self.web_view_handle.dispatch(move |webview| {
+        let web_view_handle = match &self.backend {
+            UIBackend::WebView(v) => v,
+            UIBackend::Callback(status_callback) => {
+                status_callback(status);
+                return;
+            }
+        };
+        if let Err(e) = web_view_handle.dispatch(move |webview| {
+            let status_codes = webview
+                .user_data()
+                .patcher_config
+                .window
+                .status_codes
+                .unwrap_or(false);
+            if status_codes {
+                if let Err(e) = webview.eval(&format!("patchingStatus({})", status.to_json())) {
+                    log::warn!("Failed to dispatch patching status: {}.", e);
+                }
+                return Ok(());
+            }
             let result = match status {
                 PatchingStatus::Ready => webview.eval("patchingStatusReady()"),
-                PatchingStatus::Error(msg) => {
-                    webview.eval(&format!("patchingStatusError(\"{}\")", msg))
-                }
+                PatchingStatus::Canceled => webview.eval("patchingStatusCanceled()"),
+                PatchingStatus::Error(error) => webview.eval(&format!(
+                    "patchingStatusError({}, {})",
+                    serde_json::json!(error.message),
+                    serde_json::json!(error)
+                )),
                 PatchingStatus::DownloadInProgress(nb_downloaded, nb_total, bytes_per_sec) => {
                     webview.eval(&format!(
                         "patchingStatusDownloading({}, {}, {})",
                         nb_downloaded, nb_total, bytes_per_sec
                     ))
                 }
+                PatchingStatus::DownloadMetrics(
+                    downloaded_bytes,
+                    total_bytes,
+                    bytes_per_sec,
+                    remaining_secs,
+                ) => webview.eval(&format!(
+                    "patchingStatusDownloadMetrics({}, {}, {}, {})",
+                    downloaded_bytes,
+                    serde_json::json!(total_bytes),
+                    bytes_per_sec,
+                    serde_json::json!(remaining_secs)
+                )),
+                PatchingStatus::DownloadRetry(file_name, retry, max_retries) => {
+                    webview.eval(&format!(
+                        "patchingStatusRetrying({}, {}, {})",
+                        serde_json::json!(file_name),
+                        retry,
+                        max_retries
+                    ))
+                }
+                PatchingStatus::UsingMirror(mirror_url) => webview.eval(&format!(
+                    "patchingStatusUsingMirror({})",
+                    serde_json::json!(mirror_url)
+                )),
                 PatchingStatus::InstallationInProgress(nb_installed, nb_total) => webview.eval(
                     &format!("patchingStatusInstalling({}, {})", nb_installed, nb_total),
                 ),
+                PatchingStatus::IntegrityCheckInProgress(nb_verified, nb_total) => webview.eval(
+                    &format!("patchingStatusVerifying({}, {})", nb_verified, nb_total),
+                ),
+                PatchingStatus::PatchIndexReset(patch_index) => webview.eval(&format!(
+                    "patchIndexReset({})",
+                    serde_json::json!(patch_index)
+                )),
+                PatchingStatus::PatchSimulated(patch_name, target_grf_name, file_count) => webview
+                    .eval(&format!(
+                        "patchingStatusSimulated({}, {}, {})",
+                        serde_json::json!(patch_name),
+                        serde_json::json!(target_grf_name),
+                        file_count
+                    )),
+                PatchingStatus::PendingPatches(patch_count, total_size) => {
+                    let pending_patches = serde_json::json!({
+                        "patch_count": patch_count,
+                        "total_size": total_size,
+                    });
+                    webview.eval(&format!("pendingPatchesInfo({})", pending_patches))
+                }
             };
             if let Err(e) = result {
                 log::warn!("Failed to dispatch patching status: {}.", e);
@@ -51,12 +148,135 @@ impl UIController {
     }
 }
 
+/// Prints the status of the patching process, in headless mode.
+fn print_patching_status(status: PatchingStatus) {
+    match status {
+        PatchingStatus::Ready => println!("Done"),
+        PatchingStatus::Canceled => println!("Canceled"),
+        PatchingStatus::Error(error) => eprintln!("Error: {}", error.message),
+        PatchingStatus::DownloadInProgress(nb_downloaded, nb_total, bytes_per_sec) => println!(
+            "Downloading patches: {}/{} ({} KiB/s)",
+            nb_downloaded,
+            nb_total,
+            bytes_per_sec / 1024
+        ),
+        PatchingStatus::DownloadRetry(file_name, retry, max_retries) => println!(
+            "Retrying download of '{}' ({}/{})",
+            file_name, retry, max_retries
+        ),
+        PatchingStatus::UsingMirror(mirror_url) => println!("Using mirror '{}'", mirror_url),
+        PatchingStatus::InstallationInProgress(nb_installed, nb_total) => {
+            println!("Installing patches: {}/{}", nb_installed, nb_total)
+        }
+        PatchingStatus::IntegrityCheckInProgress(nb_verified, nb_total) => {
+            println!("Verifying patches: {}/{}", nb_verified, nb_total)
+        }
+        PatchingStatus::PendingPatches(patch_count, _) => {
+            println!("{} patch(es) to download", patch_count)
+        }
+        PatchingStatus::PatchIndexReset(patch_index) => match patch_index {
+            Some(patch_index) => println!("Patch index reset to {}", patch_index),
+            None => println!("Patch index reset"),
+        },
+        PatchingStatus::PatchSimulated(patch_name, target_grf_name, file_count) => {
+            match target_grf_name {
+                Some(grf_name) => println!(
+                    "Would apply '{}' to '{}' ({} file(s))",
+                    patch_name, grf_name, file_count
+                ),
+                None => println!(
+                    "Would apply '{}' to the game directory ({} file(s))",
+                    patch_name, file_count
+                ),
+            }
+        }
+        // Note: Already reported with `DownloadInProgress`
+        PatchingStatus::DownloadMetrics(..) => {}
+    }
+}
+
 /// Used to indicate the current status of the patching process.
 pub enum PatchingStatus {
     Ready,
-    Error(String),                         // Error message
+    Canceled,                                      // Canceled by the user
+    Error(PatchingError),                          // Categorized error
     DownloadInProgress(usize, usize, u64), // Downloaded files, Total number, Bytes per second
+    DownloadRetry(String, usize, usize),   // File name, Retry number, Maximum number of retries
+    UsingMirror(String),                   // URL of the mirror in use
     InstallationInProgress(usize, usize),  // Installed patches, Total number
+    IntegrityCheckInProgress(usize, usize), // Verified patches, Total number
+    PendingPatches(usize, Option<u64>),    // Number of patches, Total size (in bytes)
+    PatchIndexReset(Option<usize>),        // Index of the last patch considered applied
+    PatchSimulated(String, Option<String>, usize), // Patch name, Target GRF, Number of files
+    // Downloaded bytes, Total bytes, Bytes per second, Remaining seconds
+    DownloadMetrics(u64, Option<u64>, u64, Option<u64>),
+}
+
+impl PatchingStatus {
+    /// Returns the status as a JSON object, identified by a stable `code`
+    /// (e.g., `{"code": "installing", "installed": 1, "total": 2}`).
+    ///
+    /// This lets UIs display their own (e.g., localized) text for each status.
+    pub fn to_json(&self) -> Value {
+        match self {
+            PatchingStatus::Ready => json!({ "code": "ready" }),
+            PatchingStatus::Canceled => json!({ "code": "canceled" }),
+            PatchingStatus::Error(error) => json!({ "code": "error", "error": error }),
+            PatchingStatus::DownloadInProgress(nb_downloaded, nb_total, bytes_per_sec) => json!({
+                "code": "downloading",
+                "downloaded": nb_downloaded,
+                "total": nb_total,
+                "bytes_per_sec": bytes_per_sec,
+            }),
+            PatchingStatus::DownloadRetry(file_name, retry, max_retries) => json!({
+                "code": "download-retry",
+                "file_name": file_name,
+                "retry": retry,
+                "max_retries": max_retries,
+            }),
+            PatchingStatus::UsingMirror(mirror_url) => json!({
+                "code": "using-mirror",
+                "mirror_url": mirror_url,
+            }),
+            PatchingStatus::InstallationInProgress(nb_installed, nb_total) => json!({
+                "code": "installing",
+                "installed": nb_installed,
+                "total": nb_total,
+            }),
+            PatchingStatus::IntegrityCheckInProgress(nb_verified, nb_total) => json!({
+                "code": "verifying",
+                "verified": nb_verified,
+                "total": nb_total,
+            }),
+            PatchingStatus::PendingPatches(patch_count, total_size) => json!({
+                "code": "pending-patches",
+                "patch_count": patch_count,
+                "total_size": total_size,
+            }),
+            PatchingStatus::PatchIndexReset(patch_index) => json!({
+                "code": "patch-index-reset",
+                "patch_index": patch_index,
+            }),
+            PatchingStatus::PatchSimulated(patch_name, target_grf_name, file_count) => json!({
+                "code": "patch-simulated",
+                "patch_name": patch_name,
+                "target_grf_name": target_grf_name,
+                "file_count": file_count,
+            }),
+            PatchingStatus::DownloadMetrics(
+                downloaded_bytes,
+                total_bytes,
+                bytes_per_sec,
+                remaining_secs,
+            ) => json!({
+                "code": "download-metrics",
+                "downloaded_bytes": downloaded_bytes,
+                "total_bytes": total_bytes,
+                "bytes_per_sec": bytes_per_sec,
+                "remaining_secs": remaining_secs,
+            }),
+        }
+    }
 }
 
 pub struct WebViewUserData {
@@ -126,8 +346,14 @@ pub fn build_webview<'a>(
                 "setup" => handle_setup(webview),
                 "exit" => handle_exit(webview),
                 "start_update" => handle_start_update(webview),
-                "cancel_update" => handle_cancel_update(webview),
+                "force_update" => handle_force_update(webview),
+                "cancel_update" | "cancel_patching" => handle_cancel_update(webview),
+                "verify_integrity" => handle_verify_integrity(webview),
+                "query_pending_patches" => handle_query_pending_patches(webview),
+                "clear_cache" => handle_clear_cache(webview),
                 "reset_cache" => handle_reset_cache(webview),
+                "get_patch_history" => handle_get_patch_history(webview),
+                "reset_patch_index" => handle_reset_patch_index(webview, None),
                 request => handle_json_request(webview, request),
             }
             Ok(())
@@ -189,6 +415,21 @@ fn handle_start_update(webview: &mut WebView<WebViewUserData>) {
     }
 }
 
+/// Starts the patching task/thread, even if the patch list hasn't changed
+/// since it was last applied.
+fn handle_force_update(webview: &mut WebView<WebViewUserData>) {
+    if block_on(
+        webview
+            .user_data_mut()
+            .patching_thread_tx
+            .send(PatcherCommand::ForceStart),
+    )
+    .is_ok()
+    {
+        log::trace!("Sent force start command to patching thread");
+    }
+}
+
 /// Cancels the patching task/thread.
 fn handle_cancel_update(webview: &mut WebView<WebViewUserData>) {
     if block_on(
@@ -203,17 +444,96 @@ fn handle_cancel_update(webview: &mut WebView<WebViewUserData>) {
     }
 }
 
+/// Starts the integrity check task, which repairs the game client's files if
+/// needed.
+fn handle_verify_integrity(webview: &mut WebView<WebViewUserData>) {
+    if block_on(
+        webview
+            .user_data_mut()
+            .patching_thread_tx
+            .send(PatcherCommand::VerifyIntegrity),
+    )
+    .is_ok()
+    {
+        log::trace!("Sent verify integrity command to patching thread");
+    }
+}
+
+/// Asks the patching task/thread for the number and size of the patches that
+/// remain to be downloaded.
+fn handle_query_pending_patches(webview: &mut WebView<WebViewUserData>) {
+    if block_on(
+        webview
+            .user_data_mut()
+            .patching_thread_tx
+            .send(PatcherCommand::QueryPendingPatches),
+    )
+    .is_ok()
+    {
+        log::trace!("Sent query pending patches command to patching thread");
+    }
+}
+
+/// Asks the patching task/thread to remove the downloaded patches kept in the
+/// download cache.
+fn handle_clear_cache(webview: &mut WebView<WebViewUserData>) {
+    if block_on(
+        webview
+            .user_data_mut()
+            .patching_thread_tx
+            .send(PatcherCommand::ClearCache),
+    )
+    .is_ok()
+    {
+        log::trace!("Sent clear cache command to patching thread");
+    }
+}
+
 /// Resets the patcher cache (which is used to keep track of already applied
 /// patches).
-fn handle_reset_cache(_webview: &mut WebView<WebViewUserData>) {
-    if let Ok(patcher_name) = get_patcher_name() {
-        let cache_file_path = PathBuf::from(patcher_name).with_extension("dat");
+fn handle_reset_cache(webview: &mut WebView<WebViewUserData>) {
+    if let Ok(cache_file_path) = get_cache_file_path(&webview.user_data().patcher_config) {
         if let Err(e) = fs::remove_file(cache_file_path) {
             log::warn!("Failed to remove the cache file: {}", e);
         }
     }
 }
 
+/// Asks the patching thread to reset the index of the last applied patch, so
+/// that the following patches are applied again (all of them if no index is
+/// given).
+fn handle_reset_patch_index(webview: &mut WebView<WebViewUserData>, patch_index: Option<usize>) {
+    if block_on(
+        webview
+            .user_data_mut()
+            .patching_thread_tx
+            .send(PatcherCommand::ResetPatchIndex(patch_index)),
+    )
+    .is_ok()
+    {
+        log::trace!("Sent reset patch index command to patching thread");
+    }
+}
+
+/// Sends the history of applied patches to the UI, from the oldest to the most
+/// recent patch.
+fn handle_get_patch_history(webview: &mut WebView<WebViewUserData>) {
+    // Note: A missing history file means that no patch has been applied yet
+    let history = get_history_file_path(&webview.user_data().patcher_config)
+        .and_then(read_history_file)
+        .unwrap_or_default();
+    let result = serde_json::to_string(&history)
+        .map_err(|e| e.to_string())
+        .and_then(|history| {
+            webview
+                .eval(&format!("patchHistory({})", history))
+                .map_err(|e| e.to_string())
+        });
+    if let Err(e) = result {
+        log::warn!("Failed to send the patch history: {}.", e);
+    }
+}
+
 /// Parses JSON requests (for invoking functions with parameters) and dispatches
 /// them to the invoked function.
 fn handle_json_request(webview: &mut WebView<WebViewUserData>, request: &str) {
@@ -228,6 +548,7 @@ fn handle_json_request(webview: &mut WebView<WebViewUserData>, request: &str) {
                 let function_params = json_req["parameters"].clone();
                 match function_name {
                     "login" => handle_login(webview, function_params),
+                    "reset_patch_index" => handle_json_reset_patch_index(webview, function_params),
                     _ => {
                         log::error!("Unknown function '{}'", function_name);
                     }
@@ -237,6 +558,21 @@ fn handle_json_request(webview: &mut WebView<WebViewUserData>, request: &str) {
     }
 }
 
+/// Parameters expected for the reset_patch_index function
+#[derive(Deserialize)]
+struct ResetPatchIndexParameters {
+    index: Option<usize>,
+}
+
+/// Resets the index of the last applied patch to the given index
+fn handle_json_reset_patch_index(webview: &mut WebView<WebViewUserData>, parameters: Value) {
+    let result: serde_json::Result<ResetPatchIndexParameters> = serde_json::from_value(parameters);
+    match result {
+        Err(e) => log::error!("Invalid arguments given for 'reset_patch_index': {}", e),
+        Ok(params) => handle_reset_patch_index(webview, params.index),
+    }
+}
+
 /// Parameters expected for the login function
 #[derive(Deserialize)]
 struct LoginParameters {
@@ -281,3 +617,54 @@ fn start_game_client(webview: &mut WebView<WebViewUserData>, client_arguments: &
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[tokio::test]
+    async fn test_dispatch_patching_status_callback() {
+        let statuses = Arc::new(Mutex::new(Vec::new()));
+        let ui_controller = {
+            let statuses = statuses.clone();
+            UIController::with_callback(move |status| {
+                let status = match status {
+                    PatchingStatus::InstallationInProgress(nb_installed, nb_total) => {
+                        format!("installing {}/{}", nb_installed, nb_total)
+                    }
+                    PatchingStatus::Ready => "ready".to_string(),
+                    _ => "other".to_string(),
+                };
+                statuses.lock().unwrap().push(status);
+            })
+        };
+        ui_controller
+            .dispatch_patching_status(PatchingStatus::InstallationInProgress(1, 2))
+            .await;
+        ui_controller
+            .dispatch_patching_status(PatchingStatus::Ready)
+            .await;
+        assert_eq!(*statuses.lock().unwrap(), vec!["installing 1/2", "ready"]);
+    }
+
+    #[test]
+    fn test_patching_status_to_json() {
+        assert_eq!(
+            PatchingStatus::InstallationInProgress(1, 2).to_json(),
+            json!({"code": "installing", "installed": 1, "total": 2})
+        );
+        assert_eq!(
+            PatchingStatus::PendingPatches(3, None).to_json(),
+            json!({"code": "pending-patches", "patch_count": 3, "total_size": null})
+        );
+        let error = PatchingError::new(&anyhow::anyhow!("Unexpected error"));
+        assert_eq!(
+            PatchingStatus::Error(error).to_json(),
+            json!({
+                "code": "error",
+                "error": {"category": "other", "message": "Unexpected error", "file_name": null}
+            })
+        );
+    }
+}